@@ -0,0 +1,146 @@
+use super::*;
+
+pub(crate) struct Cache {
+  dir: Option<Utf8PathBuf>,
+  manifests: RwLock<HashMap<Hash, Manifest>>,
+}
+
+impl Cache {
+  pub(crate) fn new(data_dir: Option<&Utf8Path>) -> Self {
+    Self {
+      dir: data_dir.map(|data_dir| data_dir.join("manifests")),
+      manifests: RwLock::default(),
+    }
+  }
+
+  pub(crate) async fn get(&self, hash: Hash) -> Option<Manifest> {
+    if let Some(manifest) = self.manifests.read().await.get(&hash) {
+      return Some(manifest.clone());
+    }
+
+    let path = self.dir.as_ref()?.join(hash.to_string());
+
+    let cbor = match tokio::fs::read(&path).await {
+      Ok(cbor) => cbor,
+      Err(err) if err.kind() == io::ErrorKind::NotFound => return None,
+      Err(err) => {
+        log::warn!("failed to read cached manifest `{path}`: {err}");
+        return None;
+      }
+    };
+
+    if Hash::bytes(&cbor) != hash {
+      log::warn!("ignoring corrupt cached manifest `{path}`");
+      return None;
+    }
+
+    let manifest = match Manifest::from_cbor(&cbor) {
+      Ok(manifest) => manifest,
+      Err(err) => {
+        log::warn!("failed to deserialize cached manifest `{path}`: {err}");
+        return None;
+      }
+    };
+
+    self.manifests.write().await.insert(hash, manifest.clone());
+
+    Some(manifest)
+  }
+
+  pub(crate) async fn insert(&self, hash: Hash, cbor: &[u8], manifest: Manifest) {
+    self.manifests.write().await.insert(hash, manifest);
+
+    if let Some(dir) = &self.dir {
+      if let Err(err) = Self::save(dir, hash, cbor).await {
+        log::warn!("failed to cache manifest {hash} in `{dir}`: {err}");
+      }
+    }
+  }
+
+  async fn save(dir: &Utf8Path, hash: Hash, cbor: &[u8]) -> io::Result<()> {
+    tokio::fs::create_dir_all(dir).await?;
+
+    let path = dir.join(hash.to_string());
+
+    if tokio::fs::try_exists(&path).await? {
+      return Ok(());
+    }
+
+    let tmp = dir.join(format!("{hash}.tmp"));
+
+    tokio::fs::write(&tmp, cbor).await?;
+
+    tokio::fs::rename(&tmp, &path).await
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn manifest() -> (Hash, Vec<u8>, Manifest) {
+    let manifest = Manifest {
+      name: "foo".into(),
+      media: Media::Comic {
+        pages: vec![Hash::bytes(b"bar")],
+      },
+      thumbnails: None,
+    };
+
+    let cbor = manifest.to_cbor();
+
+    (Hash::bytes(&cbor), cbor, manifest)
+  }
+
+  #[test]
+  fn persistent() {
+    let tempdir = tempdir();
+
+    let (hash, cbor, manifest) = manifest();
+
+    tokio::runtime::Runtime::new().unwrap().block_on(async {
+      let cache = Cache::new(Some(tempdir.path_utf8()));
+
+      assert_eq!(cache.get(hash).await, None);
+
+      cache.insert(hash, &cbor, manifest.clone()).await;
+
+      assert_eq!(cache.get(hash).await.unwrap(), manifest);
+
+      assert_eq!(
+        fs::read(tempdir.join("manifests").join(hash.to_string())).unwrap(),
+        cbor,
+      );
+
+      let cache = Cache::new(Some(tempdir.path_utf8()));
+
+      assert_eq!(cache.get(hash).await.unwrap(), manifest);
+    });
+  }
+
+  #[test]
+  fn memory() {
+    let (hash, cbor, manifest) = manifest();
+
+    tokio::runtime::Runtime::new().unwrap().block_on(async {
+      let cache = Cache::new(None);
+
+      cache.insert(hash, &cbor, manifest.clone()).await;
+
+      assert_eq!(cache.get(hash).await.unwrap(), manifest);
+    });
+  }
+
+  #[test]
+  fn corrupt_manifests_are_ignored() {
+    let tempdir = tempdir();
+
+    let (hash, _cbor, _manifest) = manifest();
+
+    tempdir.write(format!("manifests/{hash}"), "foo");
+
+    tokio::runtime::Runtime::new().unwrap().block_on(async {
+      assert_eq!(Cache::new(Some(tempdir.path_utf8())).get(hash).await, None);
+    });
+  }
+}
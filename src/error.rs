@@ -3,12 +3,47 @@ use super::*;
 #[derive(Debug, Snafu)]
 #[snafu(context(suffix(false)), visibility(pub))]
 pub(crate) enum Error {
+  #[snafu(display("invalid peer on line {line} of bootstrap file `{path}`"))]
+  BootstrapPeer {
+    backtrace: Option<Backtrace>,
+    line: usize,
+    path: Utf8PathBuf,
+    source: bootstrap::Error,
+  },
   #[snafu(display("failed to deserialize YAML package metadata at `{path}`"))]
   DeserializeMetadata {
     backtrace: Option<Backtrace>,
     path: Utf8PathBuf,
     source: serde_yaml::Error,
   },
+  #[snafu(display("failed to deserialize YAML visibility configuration at `{path}`"))]
+  DeserializeVisibility {
+    backtrace: Option<Backtrace>,
+    path: Utf8PathBuf,
+    source: serde_yaml::Error,
+  },
+  #[snafu(display("discovery group `{group}` is not a multicast address"))]
+  DiscoveryGroup {
+    backtrace: Option<Backtrace>,
+    group: IpAddr,
+  },
+  #[snafu(display("unknown discovery interface `{interface}`"))]
+  DiscoveryInterface {
+    backtrace: Option<Backtrace>,
+    interface: String,
+    source: io::Error,
+  },
+  #[snafu(display("failed to process image `{path}`"))]
+  Image {
+    backtrace: Option<Backtrace>,
+    path: Utf8PathBuf,
+    source: image::ImageError,
+  },
+  #[snafu(display("invalid JPEG `{path}`"))]
+  InvalidJpeg {
+    backtrace: Option<Backtrace>,
+    path: Utf8PathBuf,
+  },
   #[snafu(display("invalid page filename `{path}`"))]
   InvalidPage {
     backtrace: Option<Backtrace>,
@@ -53,6 +88,12 @@ pub(crate) enum Error {
     backtrace: Option<Backtrace>,
     output: Utf8PathBuf,
   },
+  #[snafu(display("failed to add `{path}` to package"))]
+  PackageAdd {
+    path: Utf8PathBuf,
+    #[snafu(backtrace)]
+    source: package::Error,
+  },
   #[snafu(display("failed to load package `{path}`"))]
   PackageLoad {
     path: Utf8PathBuf,
@@ -75,11 +116,33 @@ pub(crate) enum Error {
     backtrace: Option<Backtrace>,
     page: u64,
   },
+  #[snafu(display("listed page `{path}` not found"))]
+  PageNotFound {
+    backtrace: Option<Backtrace>,
+    path: Utf8PathBuf,
+  },
+  #[snafu(display("page `{path}` listed more than once"))]
+  PageRepeated {
+    backtrace: Option<Backtrace>,
+    path: Utf8PathBuf,
+  },
+  #[snafu(display("optimized images `{a}` and `{b}` would both be saved as `{path}`"))]
+  OptimizedPathConflict {
+    a: Utf8PathBuf,
+    b: Utf8PathBuf,
+    backtrace: Option<Backtrace>,
+    path: Utf8PathBuf,
+  },
   #[snafu(display("path contains invalid UTF-8: `{}`", path.display()))]
   PathUnicode {
     backtrace: Option<Backtrace>,
     path: PathBuf,
   },
+  #[snafu(display("--quality may not be used with lossless format {format}"))]
+  QualityUnsupported {
+    backtrace: Option<Backtrace>,
+    format: subcommand::package::optimize::Format,
+  },
   #[snafu(display("I/O error initializing async runtime"))]
   Runtime {
     backtrace: Option<Backtrace>,
@@ -91,12 +154,45 @@ pub(crate) enum Error {
     backtrace: Option<Backtrace>,
     source: io::Error,
   },
+  #[snafu(display("I/O error installing signal handler"))]
+  Signal {
+    backtrace: Option<Backtrace>,
+    source: io::Error,
+  },
+  #[snafu(display("I/O error creating staging directory"))]
+  Staging {
+    backtrace: Option<Backtrace>,
+    source: io::Error,
+  },
+  #[snafu(display("failed to deserialize stored manifest {hash}"))]
+  StoreManifest {
+    backtrace: Option<Backtrace>,
+    hash: Hash,
+    source: ciborium::de::Error<io::Error>,
+  },
+  #[snafu(display("blob {hash} missing from store"))]
+  StoreMissing {
+    backtrace: Option<Backtrace>,
+    hash: Hash,
+  },
+  #[snafu(display("stored package {hash} is invalid"))]
+  StorePackage {
+    hash: Hash,
+    #[snafu(backtrace)]
+    source: package::Error,
+  },
   #[snafu(display("unexpected file `{file}` in {ty} package"))]
   UnexpectedFile {
     backtrace: Option<Backtrace>,
     file: Utf8PathBuf,
     ty: Type,
   },
+  #[snafu(display("failed to watch library `{dir}`"))]
+  Watch {
+    backtrace: Option<Backtrace>,
+    dir: Utf8PathBuf,
+    source: notify::Error,
+  },
   #[snafu(display("failed to walk directory `{root}`"))]
   WalkDir {
     backtrace: Option<Backtrace>,
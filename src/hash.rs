@@ -15,6 +15,10 @@ impl Hash {
     Self(blake3::hash(input))
   }
 
+  pub(crate) fn keyed(key: &[u8; Self::LEN], input: &[u8]) -> Self {
+    Self(blake3::keyed_hash(key, input))
+  }
+
   pub(crate) fn reader(read: impl Read) -> io::Result<Self> {
     let mut hasher = blake3::Hasher::new();
 
@@ -61,11 +65,27 @@ impl<'de> Deserialize<'de> for Hash {
   where
     D: serde::Deserializer<'de>,
   {
-    Ok(Self(
-      serde_bytes::ByteArray::<{ Hash::LEN }>::deserialize(deserializer)?
-        .into_array()
-        .into(),
-    ))
+    struct Visitor;
+
+    impl serde::de::Visitor<'_> for Visitor {
+      type Value = Hash;
+
+      fn expecting(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{} bytes or a hexadecimal string", Hash::LEN)
+      }
+
+      fn visit_bytes<E: serde::de::Error>(self, v: &[u8]) -> Result<Self::Value, E> {
+        <[u8; Hash::LEN]>::try_from(v)
+          .map(Hash::from)
+          .map_err(|_| E::invalid_length(v.len(), &self))
+      }
+
+      fn visit_str<E: serde::de::Error>(self, v: &str) -> Result<Self::Value, E> {
+        v.parse().map_err(E::custom)
+      }
+    }
+
+    deserializer.deserialize_any(Visitor)
   }
 }
 
@@ -74,7 +94,11 @@ impl Serialize for Hash {
   where
     S: Serializer,
   {
-    serde_bytes::ByteArray::new(*self.as_bytes()).serialize(serializer)
+    if serializer.is_human_readable() {
+      serializer.collect_str(self)
+    } else {
+      serde_bytes::ByteArray::new(*self.as_bytes()).serialize(serializer)
+    }
   }
 }
 
@@ -95,4 +119,15 @@ mod tests {
     assert_eq!(hash.to_cbor(), expected);
     assert_eq!(Hash::from_cbor(&expected).unwrap(), hash);
   }
+
+  #[test]
+  fn serde_json() {
+    let hash = Hash::bytes(&[]);
+
+    let json = serde_json::to_string(&hash).unwrap();
+
+    assert_eq!(json, format!("\"{hash}\""));
+
+    assert_eq!(serde_json::from_str::<Hash>(&json).unwrap(), hash);
+  }
 }
@@ -36,9 +36,7 @@ impl<'de> Deserialize<'de> for Id {
   where
     D: serde::Deserializer<'de>,
   {
-    Ok(Self(
-      serde_bytes::ByteArray::<{ Id::LEN }>::deserialize(deserializer)?.into_array(),
-    ))
+    Ok(Self(*Hash::deserialize(deserializer)?.as_bytes()))
   }
 }
 
@@ -47,7 +45,11 @@ impl Serialize for Id {
   where
     S: Serializer,
   {
-    serde_bytes::ByteArray::new(self.0).serialize(serializer)
+    if serializer.is_human_readable() {
+      serializer.collect_str(self)
+    } else {
+      serde_bytes::ByteArray::new(self.0).serialize(serializer)
+    }
   }
 }
 
@@ -71,4 +73,15 @@ mod tests {
     assert_eq!(id.to_cbor(), expected);
     assert_eq!(Id::from_cbor(&expected).unwrap(), id);
   }
+
+  #[test]
+  fn serde_json() {
+    let id = Id::from([1; Id::LEN]);
+
+    let json = serde_json::to_string(&id).unwrap();
+
+    assert_eq!(json, format!("\"{id}\""));
+
+    assert_eq!(serde_json::from_str::<Id>(&json).unwrap(), id);
+  }
 }
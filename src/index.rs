@@ -0,0 +1,126 @@
+use super::*;
+
+#[derive(Debug, Default)]
+pub(crate) struct Index {
+  manifests: BTreeMap<Hash, Manifest>,
+  terms: BTreeMap<String, BTreeSet<Hash>>,
+}
+
+impl Index {
+  pub(crate) const MAX_RESULTS: usize = 256;
+
+  pub(crate) fn new<'a>(manifests: impl IntoIterator<Item = (Hash, &'a Manifest)>) -> Self {
+    let mut index = Self::default();
+
+    for (hash, manifest) in manifests {
+      index.insert(hash, manifest.clone());
+    }
+
+    index
+  }
+
+  pub(crate) fn contains(&self, hash: Hash) -> bool {
+    self.manifests.contains_key(&hash)
+  }
+
+  pub(crate) fn insert(&mut self, hash: Hash, manifest: Manifest) {
+    for term in Self::terms(&manifest) {
+      self.terms.entry(term).or_default().insert(hash);
+    }
+
+    self.manifests.insert(hash, manifest);
+  }
+
+  pub(crate) fn search(&self, query: &str) -> BTreeMap<Hash, Manifest> {
+    let mut matches: Option<BTreeSet<Hash>> = None;
+
+    for token in Self::tokenize(query) {
+      let hashes = self
+        .terms
+        .range(token.clone()..)
+        .take_while(|(term, _hashes)| term.starts_with(&token))
+        .flat_map(|(_term, hashes)| hashes.iter().copied())
+        .collect::<BTreeSet<Hash>>();
+
+      matches = Some(match matches {
+        Some(matches) => matches.intersection(&hashes).copied().collect(),
+        None => hashes,
+      });
+    }
+
+    matches
+      .unwrap_or_default()
+      .into_iter()
+      .take(Self::MAX_RESULTS)
+      .map(|hash| (hash, self.manifests[&hash].clone()))
+      .collect()
+  }
+
+  fn terms(manifest: &Manifest) -> BTreeSet<String> {
+    Self::tokenize(&manifest.name)
+      .chain(Self::tokenize(&manifest.media.ty().to_string()))
+      .collect()
+  }
+
+  fn tokenize(text: &str) -> impl Iterator<Item = String> + '_ {
+    text
+      .split(|c: char| !c.is_alphanumeric())
+      .filter(|token| !token.is_empty())
+      .map(str::to_lowercase)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn manifest(name: &str) -> Manifest {
+    Manifest {
+      name: name.into(),
+      media: Media::Comic { pages: Vec::new() },
+      thumbnails: None,
+    }
+  }
+
+  #[test]
+  fn search() {
+    let a = Hash::bytes(b"a");
+    let b = Hash::bytes(b"b");
+    let c = Hash::bytes(b"c");
+
+    let manifests = [
+      (a, manifest("The Amazing Spider-Man #1")),
+      (b, manifest("Spider-Woman")),
+      (c, manifest("Über Comics")),
+    ];
+
+    let index = Index::new(manifests.iter().map(|(hash, manifest)| (*hash, manifest)));
+
+    #[track_caller]
+    fn case(index: &Index, query: &str, expected: &[Hash]) {
+      assert_eq!(
+        index.search(query).into_keys().collect::<BTreeSet<Hash>>(),
+        expected.iter().copied().collect(),
+      );
+    }
+
+    case(&index, "", &[]);
+    case(&index, "   ", &[]);
+    case(&index, "spider", &[a, b]);
+    case(&index, "SPIDER", &[a, b]);
+    case(&index, "spi", &[a, b]);
+    case(&index, "spider man", &[a]);
+    case(&index, "amazing-spider", &[a]);
+    case(&index, "woman spider", &[b]);
+    case(&index, "1", &[a]);
+    case(&index, "über", &[c]);
+    case(&index, "comic", &[a, b, c]);
+    case(&index, "batman", &[]);
+    case(&index, "spider batman", &[]);
+
+    assert!(index.contains(a));
+    assert!(!index.contains(Hash::bytes(b"d")));
+
+    assert_eq!(index.search("woman")[&b], manifests[1].1);
+  }
+}
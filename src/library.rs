@@ -0,0 +1,411 @@
+use {
+  super::*,
+  crate::path_ext::PathBufExt,
+  notify::{RecommendedWatcher, RecursiveMode, Watcher},
+  std::time::SystemTime,
+  tokio::sync::mpsc,
+};
+
+#[derive(Clone, Copy, PartialEq)]
+struct Stamp {
+  len: u64,
+  modified: Option<SystemTime>,
+}
+
+#[derive(Debug, Default, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+struct Access {
+  #[serde(default, with = "serde_yaml::with::singleton_map")]
+  default: Visibility,
+  #[serde(default, with = "serde_yaml::with::singleton_map_recursive")]
+  packages: BTreeMap<String, Visibility>,
+}
+
+impl Access {
+  fn restricted() -> Self {
+    Self {
+      default: Visibility::Private,
+      packages: BTreeMap::new(),
+    }
+  }
+}
+
+pub(crate) struct Library {
+  access: Access,
+  dir: Utf8PathBuf,
+  packages: BTreeMap<Utf8PathBuf, (Stamp, Arc<Package>)>,
+}
+
+impl Library {
+  pub(crate) const DEBOUNCE: Duration = Duration::from_millis(250);
+  const EXTENSION: &'static str = "package";
+  const VISIBILITY: &'static str = "visibility.yaml";
+
+  pub(crate) fn new(dir: Utf8PathBuf) -> Self {
+    Self {
+      access: Access::default(),
+      dir,
+      packages: BTreeMap::new(),
+    }
+  }
+
+  pub(crate) fn add(&self, hash: Hash, bytes: &[u8]) -> Result<Utf8PathBuf> {
+    let path = self.dir.join(format!("{hash}.{}", Self::EXTENSION));
+
+    let mut file =
+      tempfile::NamedTempFile::new_in(&self.dir).context(error::Io { path: &self.dir })?;
+
+    file.write_all(bytes).context(error::Io { path: &path })?;
+
+    file
+      .persist(&path)
+      .map_err(|err| err.error)
+      .context(error::Io { path: &path })?;
+
+    Ok(path)
+  }
+
+  pub(crate) fn dir(&self) -> &Utf8Path {
+    &self.dir
+  }
+
+  pub(crate) fn packages(&self) -> impl Iterator<Item = &Arc<Package>> {
+    self.packages.values().map(|(_stamp, package)| package)
+  }
+
+  fn load_access(&self) -> Access {
+    let path = self.dir.join(Self::VISIBILITY);
+
+    let yaml = match fs::read_to_string(&path) {
+      Ok(yaml) => yaml,
+      Err(err) if err.kind() == io::ErrorKind::NotFound => return Access::default(),
+      Err(source) => {
+        error::Io { path }.into_error(source).report();
+        return Access::restricted();
+      }
+    };
+
+    match serde_yaml::from_str(&yaml) {
+      Ok(access) => access,
+      Err(source) => {
+        error::DeserializeVisibility { path }
+          .into_error(source)
+          .report();
+        Access::restricted()
+      }
+    }
+  }
+
+  pub(crate) fn remove(&self, hash: Hash) -> Result<bool> {
+    let mut removed = false;
+
+    for (path, (_stamp, package)) in &self.packages {
+      if package.hash == hash {
+        fs::remove_file(path).context(error::Io { path })?;
+        removed = true;
+      }
+    }
+
+    Ok(removed)
+  }
+
+  pub(crate) fn scan(&mut self) -> Result<bool> {
+    let access = self.load_access();
+
+    let mut packages = BTreeMap::new();
+
+    for entry in fs::read_dir(&self.dir).context(error::Io { path: &self.dir })? {
+      let entry = entry.context(error::Io { path: &self.dir })?;
+
+      let path = entry.path().try_into_utf8()?;
+
+      if path.extension() != Some(Self::EXTENSION) {
+        continue;
+      }
+
+      let Ok(metadata) = entry.metadata() else {
+        continue;
+      };
+
+      if !metadata.is_file() {
+        continue;
+      }
+
+      let stamp = Stamp {
+        len: metadata.len(),
+        modified: metadata.modified().ok(),
+      };
+
+      match self.packages.get(&path) {
+        Some((old, package)) if *old == stamp => {
+          packages.insert(path, (stamp, package.clone()));
+        }
+        _ => match Package::load(&path) {
+          Ok(package) => {
+            packages.insert(path, (stamp, Arc::new(package)));
+          }
+          Err(source) => error::PackageLoad { path: &path }
+            .into_error(source)
+            .report(),
+        },
+      }
+    }
+
+    let changed = packages.len() != self.packages.len()
+      || packages
+        .iter()
+        .zip(&self.packages)
+        .any(|((a, (a_stamp, _)), (b, (b_stamp, _)))| a != b || a_stamp != b_stamp)
+      || access != self.access;
+
+    self.access = access;
+    self.packages = packages;
+
+    Ok(changed)
+  }
+
+  pub(crate) fn visibility(&self) -> BTreeMap<Hash, Visibility> {
+    let mut visibility = BTreeMap::<Hash, Visibility>::new();
+
+    for (path, (_stamp, package)) in &self.packages {
+      let configured = path
+        .file_name()
+        .and_then(|name| self.access.packages.get(name))
+        .unwrap_or(&self.access.default);
+
+      // copies of a package under different names share a hash, so the
+      // most restrictive of their settings applies
+      visibility
+        .entry(package.hash)
+        .and_modify(|visibility| *visibility = visibility.restrict(configured))
+        .or_insert_with(|| configured.clone());
+    }
+
+    visibility
+  }
+
+  pub(crate) fn watch(&self) -> Result<(RecommendedWatcher, mpsc::UnboundedReceiver<()>)> {
+    let (tx, rx) = mpsc::unbounded_channel();
+
+    let mut watcher =
+      notify::recommended_watcher(move |result: notify::Result<notify::Event>| match result {
+        Ok(event) => {
+          if !event.kind.is_access() {
+            tx.send(()).ok();
+          }
+        }
+        Err(err) => log::error!("library watch error: {err}"),
+      })
+      .context(error::Watch { dir: &self.dir })?;
+
+    watcher
+      .watch(self.dir.as_std_path(), RecursiveMode::NonRecursive)
+      .context(error::Watch { dir: &self.dir })?;
+
+    Ok((watcher, rx))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn package(tempdir: &TempDir, name: &str) -> Utf8PathBuf {
+    let output = tempdir.join(name);
+
+    subcommand::package::Package {
+      compress: false,
+      optimize: Default::default(),
+      root: "tests/packages/comic".into(),
+      output: output.clone(),
+      thumbnails: false,
+    }
+    .run()
+    .unwrap();
+
+    output
+  }
+
+  #[test]
+  fn scan() {
+    let packages = tempdir();
+
+    let comic = package(&packages, "comic.package");
+
+    let library = tempdir();
+
+    let mut library = Library::new(library.path_utf8().into());
+
+    fs::create_dir_all(library.dir()).unwrap();
+
+    assert!(!library.scan().unwrap());
+    assert_eq!(library.packages().count(), 0);
+
+    fs::copy(&comic, library.dir().join("comic.package")).unwrap();
+    fs::write(library.dir().join("notes.txt"), "foo").unwrap();
+
+    assert!(library.scan().unwrap());
+    assert_eq!(library.packages().count(), 1);
+
+    assert!(!library.scan().unwrap());
+
+    fs::copy(&comic, library.dir().join("copy.package")).unwrap();
+
+    assert!(library.scan().unwrap());
+    assert_eq!(library.packages().count(), 2);
+
+    fs::remove_file(library.dir().join("comic.package")).unwrap();
+
+    assert!(library.scan().unwrap());
+    assert_eq!(library.packages().count(), 1);
+  }
+
+  #[test]
+  fn add_and_remove() {
+    let packages = tempdir();
+
+    let comic = fs::read(package(&packages, "comic.package")).unwrap();
+
+    let package = Package::from_bytes(&comic).unwrap();
+
+    let library = tempdir();
+
+    let mut library = Library::new(library.path_utf8().into());
+
+    let path = library.add(package.hash, &comic).unwrap();
+
+    assert_eq!(
+      path,
+      library.dir().join(format!("{}.package", package.hash))
+    );
+
+    assert!(library.scan().unwrap());
+    assert_eq!(library.packages().count(), 1);
+
+    fs::copy(&path, library.dir().join("copy.package")).unwrap();
+
+    assert!(library.scan().unwrap());
+    assert_eq!(library.packages().count(), 2);
+
+    assert!(!library.remove(Hash::bytes(&[])).unwrap());
+
+    assert!(library.remove(package.hash).unwrap());
+
+    assert!(library.scan().unwrap());
+    assert_eq!(library.packages().count(), 0);
+  }
+
+  #[test]
+  fn invalid_packages_are_skipped() {
+    let library = tempdir();
+
+    library.write("bad.package", "foo");
+
+    let mut library = Library::new(library.path_utf8().into());
+
+    assert!(!library.scan().unwrap());
+    assert_eq!(library.packages().count(), 0);
+  }
+
+  #[test]
+  fn missing_directory_error() {
+    let tempdir = tempdir();
+
+    let dir = tempdir.join("missing");
+
+    assert_matches!(
+      Library::new(dir.clone()).scan().unwrap_err(),
+      Error::Io { path, .. }
+      if path == dir,
+    );
+  }
+
+  #[test]
+  fn visibility() {
+    let packages = tempdir();
+
+    let comic = package(&packages, "comic.package");
+
+    let hash = Package::load(&comic).unwrap().hash;
+
+    let library = tempdir();
+
+    let mut library = Library::new(library.path_utf8().into());
+
+    fs::copy(&comic, library.dir().join("comic.package")).unwrap();
+
+    assert!(library.scan().unwrap());
+    assert_eq!(library.visibility(), [(hash, Visibility::Public)].into());
+
+    let id = "0f89ce0b671f7277b105035ea88341a81c5fceaed092eab29721fe6f86807133";
+
+    fs::write(
+      library.dir().join("visibility.yaml"),
+      format!("packages:\n  comic.package:\n    allow: [{id}]\n"),
+    )
+    .unwrap();
+
+    assert!(library.scan().unwrap());
+    assert_eq!(
+      library.visibility(),
+      [(hash, Visibility::Allow([id.parse().unwrap()].into()))].into(),
+    );
+
+    assert!(!library.scan().unwrap());
+
+    fs::write(library.dir().join("visibility.yaml"), "default: private\n").unwrap();
+
+    assert!(library.scan().unwrap());
+    assert_eq!(library.visibility(), [(hash, Visibility::Private)].into());
+
+    fs::write(library.dir().join("visibility.yaml"), "default: public\n").unwrap();
+
+    assert!(library.scan().unwrap());
+    assert_eq!(library.visibility(), [(hash, Visibility::Public)].into());
+
+    fs::write(library.dir().join("visibility.yaml"), "foo: bar\n").unwrap();
+
+    assert!(library.scan().unwrap());
+    assert_eq!(library.visibility(), [(hash, Visibility::Private)].into());
+  }
+
+  #[test]
+  fn copies_use_most_restrictive_visibility() {
+    let packages = tempdir();
+
+    let comic = package(&packages, "comic.package");
+
+    let hash = Package::load(&comic).unwrap().hash;
+
+    let library = tempdir();
+
+    let mut library = Library::new(library.path_utf8().into());
+
+    fs::copy(&comic, library.dir().join("private.package")).unwrap();
+    fs::copy(&comic, library.dir().join(format!("{hash}.package"))).unwrap();
+
+    fs::write(
+      library.dir().join("visibility.yaml"),
+      "packages:\n  private.package: private\n",
+    )
+    .unwrap();
+
+    assert!(library.scan().unwrap());
+    assert_eq!(library.visibility(), [(hash, Visibility::Private)].into());
+
+    fs::rename(
+      library.dir().join("private.package"),
+      library.dir().join("0.package"),
+    )
+    .unwrap();
+
+    fs::write(
+      library.dir().join("visibility.yaml"),
+      "packages:\n  0.package: private\n",
+    )
+    .unwrap();
+
+    assert!(library.scan().unwrap());
+    assert_eq!(library.visibility(), [(hash, Visibility::Private)].into());
+  }
+}
@@ -2,11 +2,39 @@
 
 use {
   self::{
-    deserialize_from_str::DeserializeFromStr, error::Error, from_cbor::FromCbor, hash::Hash,
-    id::Id, into_u64::IntoU64, manifest::Manifest, media::Media, message::Message,
-    metadata::Metadata, node::Node, package::Package, path_ext::PathExt, peer::Peer,
-    read_ext::ReadExt, report::Report, subcommand::Subcommand, template::Template, to_cbor::ToCbor,
-    ty::Type, write_ext::WriteExt,
+    cache::Cache,
+    deserialize_from_str::DeserializeFromStr,
+    error::Error,
+    from_cbor::FromCbor,
+    hash::Hash,
+    id::Id,
+    index::Index,
+    into_u64::IntoU64,
+    library::Library,
+    manifest::Manifest,
+    media::Media,
+    message::Message,
+    metadata::Metadata,
+    network_key::NetworkKey,
+    node::{
+      bootstrap::{self, Bootstrap},
+      contact::Contact,
+      discovery,
+      limits::{self, Limits},
+      pool, relay, throttle, Node,
+    },
+    package::Package,
+    path_ext::PathExt,
+    peer::Peer,
+    read_ext::ReadExt,
+    report::Report,
+    store::Store,
+    subcommand::Subcommand,
+    template::Template,
+    to_cbor::ToCbor,
+    ty::Type,
+    visibility::Visibility,
+    write_ext::WriteExt,
   },
   axum::{body::Body, http::header},
   boilerplate::Boilerplate,
@@ -20,16 +48,20 @@ use {
   regex::Regex,
   regex_static::{lazy_regex, once_cell::sync::Lazy},
   serde::{de::DeserializeOwned, Deserialize, Deserializer, Serialize, Serializer},
-  snafu::{ensure, ErrorCompat, OptionExt, ResultExt, Snafu},
+  snafu::{ensure, ErrorCompat, IntoError, OptionExt, ResultExt, Snafu},
   std::{
     any::Any,
     backtrace::{Backtrace, BacktraceStatus},
+    borrow::Cow,
     cmp::Ordering,
     collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    convert::Infallible,
     fmt::{self, Display, Formatter},
-    fs::File,
+    fs::{self, File},
+    future::Future,
     io::{self, BufReader, BufWriter, Cursor, Read, Seek, Write},
-    net::{AddrParseError, IpAddr, Ipv4Addr, SocketAddr},
+    iter,
+    net::{AddrParseError, IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV6},
     num::{ParseIntError, TryFromIntError},
     ops::{Deref, DerefMut},
     path::PathBuf,
@@ -39,7 +71,7 @@ use {
       atomic::{self, AtomicU64},
       Arc,
     },
-    time::Duration,
+    time::{Duration, Instant},
   },
   strum::IntoStaticStr,
   tokio::sync::RwLock,
@@ -53,16 +85,20 @@ mod test;
 #[cfg(test)]
 use test::*;
 
+mod cache;
 mod deserialize_from_str;
 mod error;
 mod from_cbor;
 mod hash;
 mod id;
+mod index;
 mod into_u64;
+mod library;
 mod manifest;
 mod media;
 mod message;
 mod metadata;
+mod network_key;
 mod node;
 mod package;
 mod passthrough;
@@ -72,10 +108,12 @@ mod re;
 mod read_ext;
 mod report;
 mod response;
+mod store;
 mod subcommand;
 mod template;
 mod to_cbor;
 mod ty;
+mod visibility;
 mod write_ext;
 
 type Result<T = (), E = Error> = std::result::Result<T, E>;
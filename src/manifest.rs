@@ -5,4 +5,77 @@ use super::*;
 pub(crate) struct Manifest {
   pub(crate) name: String,
   pub(crate) media: Media,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub(crate) thumbnails: Option<Thumbnails>,
+}
+
+impl Manifest {
+  pub(crate) fn files(&self) -> HashSet<Hash> {
+    let mut files = match &self.media {
+      Media::Comic { pages } => pages.iter().copied().collect::<HashSet<Hash>>(),
+    };
+
+    if let Some(thumbnails) = &self.thumbnails {
+      files.insert(thumbnails.cover);
+      files.extend(thumbnails.pages.iter().copied());
+    }
+
+    files
+  }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) struct Thumbnails {
+  pub(crate) cover: Hash,
+  pub(crate) pages: Vec<Hash>,
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn thumbnails_are_optional() {
+    let manifest = Manifest {
+      name: "foo".into(),
+      media: Media::Comic { pages: Vec::new() },
+      thumbnails: None,
+    };
+
+    #[derive(Serialize)]
+    struct Legacy {
+      name: String,
+      media: Media,
+    }
+
+    let legacy = Legacy {
+      name: "foo".into(),
+      media: Media::Comic { pages: Vec::new() },
+    };
+
+    assert_eq!(manifest.to_cbor(), legacy.to_cbor());
+    assert_eq!(Manifest::from_cbor(&legacy.to_cbor()).unwrap(), manifest);
+  }
+
+  #[test]
+  fn files_include_thumbnails() {
+    let page = Hash::bytes(b"page");
+    let cover = Hash::bytes(b"cover");
+    let thumbnail = Hash::bytes(b"thumbnail");
+
+    let manifest = Manifest {
+      name: "foo".into(),
+      media: Media::Comic { pages: vec![page] },
+      thumbnails: Some(Thumbnails {
+        cover,
+        pages: vec![thumbnail],
+      }),
+    };
+
+    assert_eq!(
+      manifest.files(),
+      [page, cover, thumbnail].into_iter().collect(),
+    );
+  }
 }
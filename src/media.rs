@@ -5,3 +5,11 @@ use super::*;
 pub(crate) enum Media {
   Comic { pages: Vec<Hash> },
 }
+
+impl Media {
+  pub(crate) fn ty(&self) -> Type {
+    match self {
+      Self::Comic { .. } => Type::Comic,
+    }
+  }
+}
@@ -3,7 +3,14 @@ use super::*;
 #[derive(Debug, Deserialize, Serialize, IntoStaticStr)]
 #[serde(rename_all = "snake_case")]
 pub(crate) enum Message {
+  File(Hash),
   Get(Hash),
+  Introduce(Id),
+  Observe,
   Ping,
+  Punch(Peer),
+  Query(String),
+  Relay(Id),
+  Relayed(Peer),
   Search,
 }
@@ -1,6 +1,6 @@
 use super::*;
 
-#[derive(Clone, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 #[serde(rename_all = "snake_case")]
 pub(crate) struct Metadata {
   pub(crate) name: String,
@@ -10,65 +10,327 @@ pub(crate) struct Metadata {
 impl Metadata {
   pub(crate) const PATH: &'static str = "metadata.yaml";
 
+  // formats the packager can produce with `--format`
+  pub(crate) const PAGE_EXTENSIONS: [&'static str; 5] = ["avif", "jpeg", "jpg", "png", "webp"];
+
   pub(crate) fn load(path: &Utf8Path) -> Result<Self> {
     serde_yaml::from_reader(&File::open(path).context(error::Io { path })?)
       .context(error::DeserializeMetadata { path })
   }
 
   pub(crate) fn template(self, root: &Utf8Path, paths: &HashSet<Utf8PathBuf>) -> Result<Template> {
-    let media = match &self.media {
-      Media::Comic => {
-        let mut pages: Vec<(u64, Utf8PathBuf)> = Vec::new();
-
-        for path in paths {
-          let captures = re::COMIC_PAGE
-            .captures(path.as_ref())
-            .context(error::UnexpectedFile {
-              file: path.clone(),
-              ty: self.media.ty(),
-            })?;
-
-          pages.push((
-            captures[1].parse().context(error::InvalidPage { path })?,
-            path.clone(),
-          ));
-        }
-
-        ensure!(!pages.is_empty(), error::NoPages { root });
-
-        pages.sort();
+    let ty = self.media.ty();
 
-        for (i, (page, _path)) in pages.iter().enumerate() {
-          let i = i.into_u64();
-          let page = *page;
+    let media = match self.media {
+      Media::Comic { pages } => {
+        let pages = match pages {
+          Pages::List(pages) => Self::listed_pages(pages, paths, ty)?,
+          Pages::Order(Order::Natural) => Self::natural_pages(paths, ty)?,
+          Pages::Order(Order::Numeric) => Self::numeric_pages(paths, ty)?,
+        };
 
-          ensure!(i >= page, error::PageMissing { page: i });
-          ensure!(i <= page, error::PageDuplicated { page });
-        }
+        ensure!(!pages.is_empty(), error::NoPages { root });
 
-        template::Media::Comic {
-          pages: pages.into_iter().map(|(_page, path)| path).collect(),
-        }
+        template::Media::Comic { pages }
       }
     };
 
     Ok(Template {
       media,
-      name: self.name.clone(),
+      name: self.name,
     })
   }
+
+  fn listed_pages(
+    pages: Vec<Utf8PathBuf>,
+    paths: &HashSet<Utf8PathBuf>,
+    ty: Type,
+  ) -> Result<Vec<Utf8PathBuf>> {
+    let mut listed = HashSet::new();
+
+    for page in &pages {
+      ensure!(paths.contains(page), error::PageNotFound { path: page });
+      ensure!(listed.insert(page), error::PageRepeated { path: page });
+      ensure!(
+        Self::is_page(page),
+        error::UnexpectedFile { file: page, ty }
+      );
+    }
+
+    let mut unlisted = paths
+      .iter()
+      .filter(|path| !listed.contains(path))
+      .collect::<Vec<&Utf8PathBuf>>();
+
+    unlisted.sort();
+
+    if let Some(file) = unlisted.first() {
+      return Err(error::UnexpectedFile { file: *file, ty }.build());
+    }
+
+    Ok(pages)
+  }
+
+  fn natural_pages(paths: &HashSet<Utf8PathBuf>, ty: Type) -> Result<Vec<Utf8PathBuf>> {
+    let mut pages = Vec::new();
+
+    for path in paths {
+      ensure!(
+        Self::is_page(path),
+        error::UnexpectedFile { file: path, ty }
+      );
+
+      pages.push(path.clone());
+    }
+
+    pages.sort_by(|a, b| natural_cmp(a.as_str(), b.as_str()).then_with(|| a.cmp(b)));
+
+    Ok(pages)
+  }
+
+  fn is_page(path: &Utf8Path) -> bool {
+    path
+      .extension()
+      .map(|extension| {
+        Self::PAGE_EXTENSIONS
+          .iter()
+          .any(|page| extension.eq_ignore_ascii_case(page))
+      })
+      .unwrap_or_default()
+  }
+
+  fn numeric_pages(paths: &HashSet<Utf8PathBuf>, ty: Type) -> Result<Vec<Utf8PathBuf>> {
+    let mut pages: Vec<(u64, Utf8PathBuf)> = Vec::new();
+
+    for path in paths {
+      let captures = re::NUMERIC_PAGE
+        .captures(path.as_ref())
+        .context(error::UnexpectedFile {
+          file: path.clone(),
+          ty,
+        })?;
+
+      pages.push((
+        captures[1].parse().context(error::InvalidPage { path })?,
+        path.clone(),
+      ));
+    }
+
+    pages.sort();
+
+    for (i, (page, _path)) in pages.iter().enumerate() {
+      let i = i.into_u64();
+      let page = *page;
+
+      ensure!(i >= page, error::PageMissing { page: i });
+      ensure!(i <= page, error::PageDuplicated { page });
+    }
+
+    Ok(pages.into_iter().map(|(_page, path)| path).collect())
+  }
 }
 
-#[derive(Clone, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 #[serde(rename_all = "snake_case", tag = "type")]
 pub(crate) enum Media {
-  Comic,
+  Comic {
+    #[serde(default)]
+    pages: Pages,
+  },
 }
 
 impl Media {
   pub(crate) fn ty(&self) -> Type {
     match self {
-      Self::Comic => Type::Comic,
+      Self::Comic { .. } => Type::Comic,
     }
   }
 }
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(untagged)]
+pub(crate) enum Pages {
+  List(Vec<Utf8PathBuf>),
+  Order(Order),
+}
+
+impl Default for Pages {
+  fn default() -> Self {
+    Self::Order(Order::default())
+  }
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Order {
+  Natural,
+  #[default]
+  Numeric,
+}
+
+fn natural_cmp(a: &str, b: &str) -> Ordering {
+  let mut a = a.chars().peekable();
+  let mut b = b.chars().peekable();
+
+  loop {
+    match (a.peek().copied(), b.peek().copied()) {
+      (None, None) => return Ordering::Equal,
+      (None, Some(_)) => return Ordering::Less,
+      (Some(_), None) => return Ordering::Greater,
+      (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+        let mut x = String::new();
+        while let Some(digit) = a.next_if(char::is_ascii_digit) {
+          x.push(digit);
+        }
+
+        let mut y = String::new();
+        while let Some(digit) = b.next_if(char::is_ascii_digit) {
+          y.push(digit);
+        }
+
+        let x = x.trim_start_matches('0');
+        let y = y.trim_start_matches('0');
+
+        let ordering = x.len().cmp(&y.len()).then_with(|| x.cmp(y));
+
+        if ordering != Ordering::Equal {
+          return ordering;
+        }
+      }
+      (Some(x), Some(y)) => {
+        let ordering = x.to_lowercase().cmp(y.to_lowercase());
+
+        if ordering != Ordering::Equal {
+          return ordering;
+        }
+
+        a.next();
+        b.next();
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn natural_order() {
+    #[track_caller]
+    fn case(a: &str, b: &str, ordering: Ordering) {
+      assert_eq!(natural_cmp(a, b), ordering);
+      assert_eq!(natural_cmp(b, a), ordering.reverse());
+    }
+
+    case("", "", Ordering::Equal);
+    case("a", "", Ordering::Greater);
+    case("p1.jpg", "p2.jpg", Ordering::Less);
+    case("p2.jpg", "p10.jpg", Ordering::Less);
+    case("p1.jpg", "p002.jpg", Ordering::Less);
+    case("p001.jpg", "p1.jpg", Ordering::Equal);
+    case(
+      "Issue 01 - p010.jpg",
+      "Issue 01 - p9.jpg",
+      Ordering::Greater,
+    );
+    case("A.jpg", "b.jpg", Ordering::Less);
+    case("cover.jpg", "p1.jpg", Ordering::Less);
+  }
+
+  #[test]
+  fn pages_default_to_numeric_order() {
+    let metadata = serde_yaml::from_str::<Metadata>("name: foo\nmedia:\n  type: comic\n").unwrap();
+
+    assert_matches!(
+      metadata.media,
+      Media::Comic {
+        pages: Pages::Order(Order::Numeric),
+      },
+    );
+  }
+
+  #[test]
+  fn pages_may_be_natural_order() {
+    let metadata =
+      serde_yaml::from_str::<Metadata>("name: foo\nmedia:\n  type: comic\n  pages: natural\n")
+        .unwrap();
+
+    assert_matches!(
+      metadata.media,
+      Media::Comic {
+        pages: Pages::Order(Order::Natural),
+      },
+    );
+  }
+
+  #[test]
+  fn page_orders_accept_the_same_formats() {
+    fn case(pages: Pages, paths: &[&str]) -> Result<Vec<Utf8PathBuf>> {
+      let metadata = Metadata {
+        name: "foo".into(),
+        media: Media::Comic { pages },
+      };
+
+      let paths = paths.iter().copied().map(Utf8PathBuf::from).collect();
+
+      let template::Media::Comic { pages } = metadata.template("root".into(), &paths)?.media;
+
+      Ok(pages)
+    }
+
+    let images = ["a.JPEG", "b.png", "c.webp", "d.avif", "e.jpg"];
+
+    assert_eq!(case(Pages::Order(Order::Natural), &images).unwrap(), images,);
+
+    assert_eq!(
+      case(
+        Pages::List(images.iter().rev().copied().map(Into::into).collect()),
+        &images,
+      )
+      .unwrap(),
+      images.iter().rev().copied().collect::<Vec<&str>>(),
+    );
+
+    let numbered = ["0.JPEG", "1.png", "2.webp", "3.avif", "4.jpg"];
+
+    assert_eq!(
+      case(Pages::Order(Order::Numeric), &numbered).unwrap(),
+      numbered,
+    );
+
+    assert_matches!(
+      case(Pages::Order(Order::Numeric), &["0.jpg", "1.txt"]),
+      Err(Error::UnexpectedFile { file, .. }) if file == "1.txt",
+    );
+
+    assert_matches!(
+      case(Pages::Order(Order::Natural), &["a.jpg", "notes.txt"]),
+      Err(Error::UnexpectedFile { file, .. }) if file == "notes.txt",
+    );
+
+    assert_matches!(
+      case(
+        Pages::List(vec!["a.jpg".into(), "notes.txt".into()]),
+        &["a.jpg", "notes.txt"],
+      ),
+      Err(Error::UnexpectedFile { file, .. }) if file == "notes.txt",
+    );
+  }
+
+  #[test]
+  fn pages_may_be_listed() {
+    let metadata = serde_yaml::from_str::<Metadata>(
+      "name: foo\nmedia:\n  type: comic\n  pages:\n  - b.jpg\n  - a.jpg\n",
+    )
+    .unwrap();
+
+    assert_matches!(
+      metadata.media,
+      Media::Comic {
+        pages: Pages::List(pages),
+      }
+      if pages == ["b.jpg", "a.jpg"],
+    );
+  }
+}
@@ -0,0 +1,59 @@
+use super::*;
+
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) struct NetworkKey([u8; Hash::LEN]);
+
+impl NetworkKey {
+  const CONTEXT: &'static str = "gossamer 2024-09-01 network key";
+
+  pub(crate) fn mac(&self, label: &str, ids: &[Id], data: &[u8]) -> Hash {
+    let mut message = Vec::new();
+    message.extend_from_slice(label.as_bytes());
+    message.push(0);
+    for id in ids {
+      message.extend_from_slice(id.as_bytes());
+    }
+    message.extend_from_slice(data);
+    Hash::keyed(&self.0, &message)
+  }
+}
+
+impl fmt::Debug for NetworkKey {
+  fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+    f.write_str("NetworkKey(..)")
+  }
+}
+
+impl FromStr for NetworkKey {
+  type Err = Infallible;
+
+  fn from_str(secret: &str) -> Result<Self, Self::Err> {
+    Ok(Self(blake3::derive_key(Self::CONTEXT, secret.as_bytes())))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn mac() {
+    let a = "foo".parse::<NetworkKey>().unwrap();
+    let b = "bar".parse::<NetworkKey>().unwrap();
+
+    let id = Id::from([1; Id::LEN]);
+
+    assert_eq!(a.mac("x", &[id], b"y"), a.mac("x", &[id], b"y"));
+    assert_ne!(a.mac("x", &[id], b"y"), b.mac("x", &[id], b"y"));
+    assert_ne!(a.mac("x", &[id], b"y"), a.mac("z", &[id], b"y"));
+    assert_ne!(a.mac("x", &[id], b"y"), a.mac("x", &[], b"y"));
+  }
+
+  #[test]
+  fn debug_does_not_reveal_key() {
+    assert_eq!(
+      format!("{:?}", "foo".parse::<NetworkKey>().unwrap()),
+      "NetworkKey(..)",
+    );
+  }
+}
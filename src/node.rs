@@ -1,4 +1,22 @@
-use super::*;
+use {
+  super::*,
+  limits::Limits,
+  pool::Pool,
+  relay::Relay,
+  throttle::Throttle,
+  tokio::{
+    sync::{OwnedSemaphorePermit, Semaphore},
+    task::JoinSet,
+  },
+};
+
+pub(crate) mod bootstrap;
+pub(crate) mod contact;
+pub(crate) mod discovery;
+pub(crate) mod limits;
+pub(crate) mod pool;
+pub(crate) mod relay;
+pub(crate) mod throttle;
 
 #[derive(Debug, Snafu)]
 #[snafu(context(suffix(Error)))]
@@ -8,11 +26,19 @@ pub(crate) enum Error {
     backtrace: Option<Backtrace>,
     source: quinn::ConnectionError,
   },
+  Bootstrap {
+    backtrace: Option<Backtrace>,
+    source: bootstrap::Error,
+  },
   Connect {
     backtrace: Option<Backtrace>,
     peer: Peer,
     source: quinn::ConnectError,
   },
+  ConnectTimeout {
+    backtrace: Option<Backtrace>,
+    peer: Peer,
+  },
   Connection {
     backtrace: Option<Backtrace>,
     peer: Peer,
@@ -27,15 +53,77 @@ pub(crate) enum Error {
     backtrace: Option<Backtrace>,
     source: io::Error,
   },
+  Finish {
+    backtrace: Option<Backtrace>,
+    peer: Peer,
+    source: quinn::ClosedStream,
+  },
+  FileHash {
+    actual: Hash,
+    backtrace: Option<Backtrace>,
+    expected: Hash,
+    peer: Peer,
+  },
+  FileLength {
+    backtrace: Option<Backtrace>,
+    len: u64,
+    peer: Peer,
+  },
+  IdentityMismatch {
+    actual: Id,
+    backtrace: Option<Backtrace>,
+    peer: Peer,
+  },
+  InvalidManifest {
+    backtrace: Option<Backtrace>,
+    hash: Hash,
+    peer: Peer,
+    source: ciborium::de::Error<io::Error>,
+  },
+  ManifestHash {
+    actual: Hash,
+    backtrace: Option<Backtrace>,
+    expected: Hash,
+    peer: Peer,
+  },
+  MessageLength {
+    backtrace: Option<Backtrace>,
+    len: usize,
+    peer: Peer,
+  },
+  PeerTableDeserialize {
+    backtrace: Option<Backtrace>,
+    path: Utf8PathBuf,
+    source: serde_json::Error,
+  },
+  PeerTableRead {
+    backtrace: Option<Backtrace>,
+    path: Utf8PathBuf,
+    source: io::Error,
+  },
+  PeerTableWrite {
+    backtrace: Option<Backtrace>,
+    path: Utf8PathBuf,
+    source: io::Error,
+  },
   Read {
     backtrace: Option<Backtrace>,
     peer: Peer,
     source: quinn::ReadExactError,
   },
-  Stop {
+  RelayRefused {
+    backtrace: Option<Backtrace>,
+    peer: Peer,
+    relay: Peer,
+  },
+  RequestTimeout {
     backtrace: Option<Backtrace>,
     peer: Peer,
-    source: quinn::StoppedError,
+  },
+  UnexpectedResponse {
+    backtrace: Option<Backtrace>,
+    message: &'static str,
+    peer: Peer,
   },
   Write {
     backtrace: Option<Backtrace>,
@@ -44,31 +132,57 @@ pub(crate) enum Error {
   },
 }
 
-#[derive(Clone, Copy, Serialize)]
-#[serde(rename_all = "snake_case")]
-enum Status {
-  Done,
-}
+impl Error {
+  pub(crate) fn is_timeout(&self) -> bool {
+    matches!(
+      self,
+      Self::ConnectTimeout { .. } | Self::RequestTimeout { .. }
+    )
+  }
 
-impl Status {
-  fn error_code(self) -> quinn::VarInt {
-    quinn::VarInt::from_u32(match self {
-      Self::Done => 0,
-    })
+  fn is_transient(&self) -> bool {
+    matches!(
+      self,
+      Self::Connect { .. }
+        | Self::ConnectTimeout { .. }
+        | Self::Connection { .. }
+        | Self::Finish { .. }
+        | Self::Read { .. }
+        | Self::RequestTimeout { .. }
+        | Self::Write { .. }
+    )
   }
 }
 
 type Result<T = (), E = Error> = std::result::Result<T, E>;
 
 pub(crate) struct Node {
+  cache: Cache,
+  connections: Arc<Semaphore>,
+  download: Throttle,
   endpoint: Endpoint,
   id: Id,
   ip: IpAddr,
+  keep_alive: quinn::ClientConfig,
+  limits: Limits,
   pub(crate) port: u16,
   pub(crate) received: AtomicU64,
-  pub(crate) local: RwLock<HashMap<Id, Peer>>,
+  pub(crate) local: RwLock<HashMap<Id, Contact>>,
+  network_key: Option<NetworkKey>,
+  observed: RwLock<HashMap<Id, SocketAddr>>,
+  peer_table: Option<Utf8PathBuf>,
   pub(crate) sent: AtomicU64,
-  pub(crate) packages: Arc<BTreeMap<Hash, Package>>,
+  index: RwLock<Arc<Index>>,
+  packages: RwLock<Arc<BTreeMap<Hash, Arc<Package>>>>,
+  pinned: RwLock<Arc<BTreeSet<Hash>>>,
+  pool: Pool,
+  punches: Arc<Semaphore>,
+  relay: Option<Arc<Relay>>,
+  relayed: RwLock<HashMap<Id, Peer>>,
+  remote: RwLock<BTreeMap<Id, Index>>,
+  store: Option<Store>,
+  upload: Throttle,
+  visibility: RwLock<Arc<BTreeMap<Hash, Visibility>>>,
 }
 
 fn random_id() -> Id {
@@ -77,132 +191,192 @@ fn random_id() -> Id {
 }
 
 impl Node {
+  const BOOTSTRAP_RETRY_DELAY: Duration = Duration::from_secs(1);
+  const CONNECT_TIMEOUT: Duration = Duration::from_millis(if cfg!(test) { 100 } else { 5_000 });
+  const FILE_TIMEOUT: Duration = Duration::from_secs(300);
+  const HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+  const IDLE_TIMEOUT: Duration = Duration::from_millis(if cfg!(test) { 1_000 } else { 60_000 });
+  const MAX_ATTEMPTS: u32 = 3;
+  const MAX_BOOTSTRAP_RETRY_DELAY: Duration = Duration::from_secs(5 * 60);
+  const MAX_FAILURES: u32 = 3;
+  const MAX_FILE_LEN: u64 = 1 << 30;
+  const MAX_PENDING_PUNCHES: usize = 16;
+  const MAX_RENDEZVOUS_ATTEMPTS: usize = 3;
+  const REQUEST_TIMEOUT: Duration = Duration::from_millis(if cfg!(test) { 250 } else { 10_000 });
+  const RETRY_DELAY: Duration = Duration::from_millis(100);
+
   pub(crate) async fn new(
     address: IpAddr,
-    packages: BTreeMap<Hash, Package>,
+    data_dir: Option<&Utf8Path>,
+    limits: Limits,
+    network_key: Option<NetworkKey>,
+    packages: BTreeMap<Hash, Arc<Package>>,
     port: u16,
+    relay: Option<relay::Config>,
   ) -> Result<Self> {
     let id = random_id();
 
-    let endpoint = passthrough::Session::endpoint(id, address, port);
+    let endpoint =
+      passthrough::Session::endpoint(id, network_key, address, port, limits.max_streams);
 
     let socket_address = endpoint.local_addr().context(LocalAddressError)?;
 
     Ok(Self {
+      cache: Cache::new(data_dir),
+      connections: Arc::new(Semaphore::new(limits.max_connections)),
+      download: Throttle::new(limits.download),
       endpoint,
       id,
       ip: socket_address.ip(),
-      packages: Arc::new(packages),
+      keep_alive: passthrough::Session::keep_alive(id, network_key, limits.max_streams),
+      limits,
+      index: RwLock::new(Arc::new(Self::index(&packages))),
+      packages: RwLock::new(Arc::new(packages)),
+      pinned: RwLock::default(),
+      pool: Pool::default(),
+      punches: Arc::new(Semaphore::new(Self::MAX_PENDING_PUNCHES)),
+      relay: relay.map(|config| Arc::new(Relay::new(config))),
+      relayed: RwLock::default(),
+      remote: RwLock::default(),
+      store: data_dir.map(Store::new),
+      upload: Throttle::new(limits.upload),
+      visibility: RwLock::default(),
       port: socket_address.port(),
       received: AtomicU64::default(),
       sent: AtomicU64::default(),
       local: RwLock::default(),
+      network_key,
+      observed: RwLock::default(),
+      peer_table: data_dir.map(|data_dir| data_dir.join("peers.json")),
     })
   }
 
-  pub(crate) fn peer(&self) -> Peer {
-    Peer {
-      id: self.id,
-      ip: self.ip,
-      port: self.port,
-    }
+  pub(crate) async fn packages(&self) -> Arc<BTreeMap<Hash, Arc<Package>>> {
+    self.packages.read().await.clone()
   }
 
-  pub(crate) async fn run(self: Arc<Self>) -> Result {
-    const MULTICAST_IP: Ipv4Addr = Ipv4Addr::new(239, 4, 9, 151);
-    // generic multicast application port:
-    // https://datatracker.ietf.org/doc/draft-karstens-pim-multicast-application-ports/
-    const MULTICAST_PORT: u16 = 49151;
-    const MULTICAST_ADDR: SocketAddr = SocketAddr::new(IpAddr::V4(MULTICAST_IP), MULTICAST_PORT);
-
-    log::info!("local peer: {}", self.peer());
+  pub(crate) async fn pinned(&self) -> Arc<BTreeSet<Hash>> {
+    self.pinned.read().await.clone()
+  }
 
-    #[derive(Deserialize, Serialize)]
-    struct Advertisement {
-      id: Id,
-      port: u16,
+  pub(crate) async fn set_packages(
+    &self,
+    packages: BTreeMap<Hash, Arc<Package>>,
+    pinned: BTreeSet<Hash>,
+    visibility: BTreeMap<Hash, Visibility>,
+  ) {
+    if self.network_key.is_none()
+      && visibility
+        .values()
+        .any(|visibility| matches!(visibility, Visibility::Allow(_)))
+    {
+      log::warn!("allow lists require a network key, since peer ids are otherwise unauthenticated");
     }
 
-    let socket = {
-      use socket2::{Domain, Protocol, Socket, Type};
-
-      let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP)).unwrap();
-
-      #[cfg(unix)]
-      socket.set_reuse_port(true).unwrap();
+    let mut guard = self.packages.write().await;
+    *self.index.write().await = Arc::new(Self::index(&packages));
+    *self.pinned.write().await = Arc::new(pinned);
+    *self.visibility.write().await = Arc::new(visibility);
+    *guard = Arc::new(packages);
+  }
 
-      socket
-        .bind(&SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), MULTICAST_PORT).into())
-        .unwrap();
+  pub(crate) async fn visibility(&self) -> Arc<BTreeMap<Hash, Visibility>> {
+    self.visibility.read().await.clone()
+  }
 
-      let socket = std::net::UdpSocket::from(socket);
+  async fn shared(&self, id: Option<Id>) -> BTreeMap<Hash, Arc<Package>> {
+    let packages = self.packages.read().await;
+    let visibility = self.visibility.read().await;
 
-      let socket = tokio::net::UdpSocket::from_std(socket).unwrap();
+    packages
+      .iter()
+      .filter(|(hash, _package)| {
+        visibility
+          .get(hash)
+          .is_none_or(|visibility| visibility.allows(id))
+      })
+      .map(|(hash, package)| (*hash, package.clone()))
+      .collect()
+  }
 
-      socket
-        .join_multicast_v4(MULTICAST_IP, Ipv4Addr::UNSPECIFIED)
-        .unwrap();
+  fn index(packages: &BTreeMap<Hash, Arc<Package>>) -> Index {
+    Index::new(
+      packages
+        .iter()
+        .map(|(hash, package)| (*hash, &package.manifest)),
+    )
+  }
 
-      socket
-    };
+  pub(crate) async fn query_local(&self, query: &str) -> BTreeMap<Hash, Manifest> {
+    self.index.read().await.search(query)
+  }
 
-    let tx = Arc::new(socket);
-    let rx = tx.clone();
-    let id = self.id;
-    let port = self.port;
+  pub(crate) async fn query_remote(&self, query: &str) -> BTreeMap<Id, BTreeMap<Hash, Manifest>> {
+    self
+      .remote
+      .read()
+      .await
+      .iter()
+      .map(|(id, index)| (*id, index.search(query)))
+      .filter(|(_id, results)| !results.is_empty())
+      .collect()
+  }
 
-    let node = self.clone();
-    tokio::spawn(async move {
-      loop {
-        let mut buf = [0u8; u16::MAX as usize];
+  pub(crate) async fn indexed(&self, id: Id, hash: Hash) -> bool {
+    self
+      .remote
+      .read()
+      .await
+      .get(&id)
+      .is_some_and(|index| index.contains(hash))
+  }
 
-        let (len, src) = rx.recv_from(&mut buf).await.unwrap();
+  pub(crate) fn store(&self) -> Option<&Store> {
+    self.store.as_ref()
+  }
 
-        let advertisement = Advertisement::from_cbor(&buf[..len]).unwrap();
+  pub(crate) fn peer(&self) -> Peer {
+    Peer {
+      id: self.id,
+      ip: self.ip,
+      port: self.port,
+      scope_id: 0,
+    }
+  }
 
-        let peer = Peer {
-          id: advertisement.id,
-          ip: src.ip(),
-          port: advertisement.port,
-        };
+  pub(crate) async fn run(self: Arc<Self>, discovery: Option<discovery::Config>) -> Result {
+    log::info!("local peer: {}", self.peer());
 
-        if peer.id == id {
-          eprintln!("ignoring advertisement from self");
-        } else {
-          eprintln!("advertisement from peer: {peer}");
-          node.ping(peer).await.unwrap();
+    if let Some(config) = &discovery {
+      for &group in &config.groups {
+        match discovery::socket(group, config) {
+          Ok(socket) => {
+            let socket = Arc::new(socket);
+            tokio::spawn(discovery::listen(self.clone(), socket.clone()));
+            tokio::spawn(discovery::advertise(
+              self.clone(),
+              socket,
+              config.group_addr(group),
+            ));
+          }
+          Err(err) => log::warn!("multicast discovery disabled for group {group}: {err}"),
         }
       }
-    });
-
-    tokio::spawn(async move {
-      let advertisement = Advertisement { id, port }.to_cbor();
-
-      // make a local connection to ensure we are accepting incoming
-      // connections before sending our first advertisement
-      let endpoint = passthrough::Session::endpoint(random_id(), Ipv4Addr::LOCALHOST.into(), 0);
-
-      let peer = Peer {
-        id,
-        port,
-        ip: Ipv4Addr::LOCALHOST.into(),
-      };
-
-      endpoint
-        .connect((Ipv4Addr::LOCALHOST, port).into(), &id.to_string())
-        .context(ConnectError { peer })
-        .unwrap()
-        .await
-        .context(ConnectionError { peer })
-        .unwrap();
-
-      loop {
-        tx.send_to(&advertisement, &MULTICAST_ADDR).await.unwrap();
+    } else {
+      log::info!("multicast discovery disabled");
+    }
 
-        tokio::time::sleep(Duration::from_secs(5 * 60)).await;
+    tokio::spawn({
+      let node = self.clone();
+      async move {
+        if let Err(err) = node.restore_peers().await {
+          err.report();
+        }
       }
     });
 
+    tokio::spawn(self.clone().monitor());
+
     log::info!("listening for incoming connections");
     while let Some(incoming) = self.endpoint.accept().await {
       log::info!("accepted incoming connection");
@@ -220,6 +394,12 @@ impl Node {
   async fn accept(self: Arc<Self>, incoming: Incoming) -> Result {
     let address = incoming.remote_address();
 
+    let Ok(permit) = self.connections.clone().try_acquire_owned() else {
+      log::warn!("refusing connection from {address}: too many connections");
+      incoming.refuse();
+      return Ok(());
+    };
+
     let connection = incoming
       .accept()
       .context(AcceptError { address })?
@@ -228,22 +408,138 @@ impl Node {
 
     let socket_addr = connection.remote_address();
 
-    let peer = Peer {
-      ip: socket_addr.ip(),
-      id: passthrough::Session::peer_identity(&connection),
-      port: socket_addr.port(),
-    };
+    let peer = Peer::new(
+      passthrough::Session::peer_identity(&connection),
+      socket_addr,
+    );
 
-    let (mut tx, rx) = connection
-      .accept_bi()
-      .await
-      .context(AcceptError { address })?;
+    let member = passthrough::Session::is_member(&connection);
+
+    // inbound ids are only claimed unless authenticated by the network key
+    if member {
+      self.pool.insert(peer.id, connection.clone());
+    } else {
+      self.pool.accept(peer.id, connection.clone());
+    }
+
+    let result = self.serve(connection, peer, member, false).await;
+
+    drop(permit);
+
+    result
+  }
+
+  async fn serve(
+    self: Arc<Self>,
+    connection: Connection,
+    peer: Peer,
+    member: bool,
+    dialed: bool,
+  ) -> Result {
+    let address = peer.socket_addr();
+
+    let mut activity = Self::activity(&connection);
+
+    loop {
+      let accepted = match tokio::time::timeout(Self::IDLE_TIMEOUT, connection.accept_bi()).await {
+        Ok(accepted) => accepted,
+        Err(_) => {
+          let current = Self::activity(&connection);
+
+          // keep-alives do not count, so idle connections are closed even if
+          // the remote keeps them alive, releasing their pool slot and permit
+          if current == activity {
+            log::debug!("closing idle connection to {peer}");
+            connection.close(0u32.into(), b"idle");
+            self.pool.remove(peer.id, &connection);
+            return Ok(());
+          }
+
+          activity = current;
+          continue;
+        }
+      };
+
+      let (tx, rx) = match accepted {
+        Ok(stream) => stream,
+        Err(
+          quinn::ConnectionError::ApplicationClosed(_)
+          | quinn::ConnectionError::ConnectionClosed(_)
+          | quinn::ConnectionError::LocallyClosed
+          | quinn::ConnectionError::TimedOut,
+        ) => return Ok(()),
+        Err(source) => return Err(source).context(AcceptError { address }),
+      };
+
+      let node = self.clone();
+      tokio::spawn(async move {
+        if let Err(err) = node.respond(peer, member, dialed, tx, rx).await {
+          err.report();
+        }
+      });
+    }
+  }
+
+  async fn respond(
+    self: Arc<Self>,
+    peer: Peer,
+    member: bool,
+    dialed: bool,
+    mut tx: SendStream,
+    mut rx: RecvStream,
+  ) -> Result {
+    let mut message = self.read(peer, &mut rx).await?;
+
+    // the id that allow lists are checked against, which is only trusted
+    // from members of our network, since ids are otherwise unauthenticated
+    let mut client = member.then_some(peer.id);
+
+    if let Message::Relayed(origin) = message {
+      // only relays we connected to ourselves may forward requests to us
+      if !dialed {
+        log::debug!("refusing request relayed by {peer}: not a relay we connected to");
+        tx.reset(0u32.into()).ok();
+        rx.stop(0u32.into()).ok();
+        return Ok(());
+      }
+
+      log::debug!("received request from {origin} relayed by {peer}");
+
+      // the relay can claim any origin, so only trust it if the relay is a
+      // member of our network
+      client = member.then_some(origin.id);
 
-    let message = self.receive(peer, rx).await?;
+      message = self.read(peer, &mut rx).await?;
+    }
 
     self.received.fetch_add(1, atomic::Ordering::Relaxed);
 
     match message {
+      Message::File(hash) => {
+        let content = self
+          .shared(client)
+          .await
+          .values()
+          .find_map(|package| package.files.get(&hash))
+          .map(|content| content.decompress().into_owned());
+
+        self
+          .write(
+            peer,
+            &mut tx,
+            response::File(content.as_ref().map(|content| content.len().into_u64())),
+          )
+          .await?;
+
+        if let Some(content) = content {
+          for chunk in content.chunks(throttle::CHUNK_LEN) {
+            self.upload.acquire(peer.ip, chunk.len()).await;
+            tx.write_all(chunk).await.context(WriteError { peer })?;
+          }
+        }
+
+        tx.finish().context(FinishError { peer })?;
+      }
       Message::Get(hash) => {
         self
           .send(
@@ -251,37 +547,163 @@ impl Node {
             &mut tx,
             response::Get(
               self
-                .packages
+                .shared(client)
+                .await
                 .get(&hash)
                 .map(|package| package.manifest.to_cbor()),
             ),
           )
           .await?;
+      }
+      Message::Introduce(id) => {
+        let introduced = if self.network_key.is_none() || member {
+          self.introduce(peer, id).await
+        } else {
+          None
+        };
 
-        Self::finish(connection, peer, Status::Done, tx).await?;
+        self
+          .send(peer, &mut tx, response::Introduce(introduced))
+          .await?;
       }
-      Message::Ping => self.send(peer, &mut tx, response::Ping).await?,
-      Message::Search => {
+      Message::Observe => {
         self
-          .send(
-            peer,
-            &mut tx,
-            response::Search(self.packages.keys().cloned().collect()),
-          )
+          .send(peer, &mut tx, response::Observe(peer.socket_addr()))
           .await?;
-        Self::finish(connection, peer, Status::Done, tx).await?;
       }
+      Message::Ping => self.send(peer, &mut tx, response::Ping).await?,
+      Message::Punch(target) => {
+        self.send(peer, &mut tx, response::Punch).await?;
+
+        if self.network_key.is_none() || member {
+          match self.punches.clone().try_acquire_owned() {
+            Ok(permit) => {
+              tokio::spawn(async move {
+                self.punched(target).await;
+                drop(permit);
+              });
+            }
+            Err(_) => log::debug!("ignoring punch request from {peer}: too many pending punches"),
+          }
+        }
+      }
+      Message::Query(query) => {
+        let shared = self.shared(client).await;
+
+        let results = self
+          .query_local(&query)
+          .await
+          .into_keys()
+          .filter(|hash| shared.contains_key(hash))
+          .collect();
+
+        self.send(peer, &mut tx, response::Query(results)).await?;
+      }
+      Message::Relay(id) => self.forward(peer, member, id, tx, rx).await?,
+      Message::Relayed(origin) => {
+        log::debug!("ignoring nested relay request from {origin} via {peer}");
+      }
+      Message::Search => {
+        let packages = if self.network_key.is_none() || member {
+          self.shared(client).await.into_keys().collect()
+        } else {
+          Vec::new()
+        };
+
+        self.send(peer, &mut tx, response::Search(packages)).await?;
+      }
+    }
+
+    Ok(())
+  }
+
+  async fn forward(
+    &self,
+    peer: Peer,
+    member: bool,
+    id: Id,
+    mut tx: SendStream,
+    rx: RecvStream,
+  ) -> Result {
+    let Some((relay, permit, target, mut target_tx, target_rx)) =
+      self.open_forward(peer, member, id).await
+    else {
+      return self.send(peer, &mut tx, response::Relay(false)).await;
+    };
+
+    if let Err(err) = self
+      .write(target, &mut target_tx, Message::Relayed(peer))
+      .await
+    {
+      log::debug!("failed to relay from {peer} to {target}: {err}");
+      return self.send(peer, &mut tx, response::Relay(false)).await;
     }
 
+    self.write(peer, &mut tx, response::Relay(true)).await?;
+
+    log::debug!("relaying from {peer} to {target}");
+
+    let upstream = tokio::spawn({
+      let relay = relay.clone();
+      async move { relay.pipe(peer.ip, rx, target_tx).await }
+    });
+
+    relay.pipe(peer.ip, target_rx, tx).await;
+
+    upstream.await.ok();
+
+    drop(permit);
+
     Ok(())
   }
 
+  async fn open_forward(
+    &self,
+    peer: Peer,
+    member: bool,
+    id: Id,
+  ) -> Option<(
+    Arc<Relay>,
+    OwnedSemaphorePermit,
+    Peer,
+    SendStream,
+    RecvStream,
+  )> {
+    let relay = self.relay.clone()?;
+
+    if self.network_key.is_some() && !member || id == peer.id || id == self.id {
+      return None;
+    }
+
+    let connection = self.pool.reachable(id)?;
+
+    let permit = relay.permit()?;
+
+    let target = Peer::new(id, connection.remote_address());
+
+    let (tx, rx) = connection.open_bi().await.ok()?;
+
+    Some((relay, permit, target, tx, rx))
+  }
+
   async fn send<T: Serialize>(&self, peer: Peer, stream: &mut SendStream, message: T) -> Result {
-    let message = message.to_cbor();
+    self.write(peer, stream, message).await?;
+
+    stream.finish().context(FinishError { peer })?;
 
-    assert!(message.len() < u16::MAX as usize);
+    Ok(())
+  }
+
+  async fn write<T: Serialize>(&self, peer: Peer, stream: &mut SendStream, message: T) -> Result {
+    let message = message.to_cbor();
 
-    let len = message.len() as u16;
+    let len = u16::try_from(message.len()).map_err(|_| {
+      MessageLengthError {
+        len: message.len(),
+        peer,
+      }
+      .build()
+    })?;
 
     stream
       .write_all(&len.to_le_bytes())
@@ -293,14 +715,12 @@ impl Node {
       .await
       .context(WriteError { peer })?;
 
-    stream.stopped().await.context(StopError { peer })?;
-
     self.sent.fetch_add(1, atomic::Ordering::Relaxed);
 
     Ok(())
   }
 
-  async fn receive<T: DeserializeOwned>(&self, peer: Peer, mut rx: RecvStream) -> Result<T> {
+  async fn read<T: DeserializeOwned>(&self, peer: Peer, rx: &mut RecvStream) -> Result<T> {
     let mut len = [0; 2];
 
     rx.read_exact(&mut len).await.context(ReadError { peer })?;
@@ -316,122 +736,2046 @@ impl Node {
     T::from_cbor(&buffer).context(DeserializeError { peer })
   }
 
-  pub(crate) async fn connect(&self, peer: Peer) -> Result<Connection> {
-    let connection = self
-      .endpoint
-      .connect(peer.socket_addr(), &peer.id.to_string())
-      .context(ConnectError { peer })?
-      .await
-      .context(ConnectionError { peer })?;
+  pub(crate) async fn connect(self: &Arc<Self>, peer: Peer) -> Result<Connection> {
+    match self.dial(peer, false).await {
+      Err(err) if err.is_timeout() => match self.punch(peer).await {
+        Some(connection) => Ok(connection),
+        None => Err(err),
+      },
+      result => result,
+    }
+  }
 
-    assert_eq!(passthrough::Session::peer_identity(&connection), peer.id);
+  async fn dial(self: &Arc<Self>, peer: Peer, keep_alive: bool) -> Result<Connection> {
+    let connecting = if keep_alive {
+      self.endpoint.connect_with(
+        self.keep_alive.clone(),
+        peer.socket_addr(),
+        &peer.id.to_string(),
+      )
+    } else {
+      self
+        .endpoint
+        .connect(peer.socket_addr(), &peer.id.to_string())
+    }
+    .context(ConnectError { peer })?;
 
-    Ok(connection)
-  }
+    let connection = tokio::time::timeout(Self::CONNECT_TIMEOUT, connecting)
+      .await
+      .map_err(|_| ConnectTimeoutError { peer }.build())?
+      .context(ConnectionError { peer })?;
 
-  pub(crate) async fn ping(&self, peer: Peer) -> Result {
-    log::debug!("pinging {peer}");
+    let actual = passthrough::Session::peer_identity(&connection);
 
-    self.check(peer).await?;
+    if actual != peer.id {
+      connection.close(0u32.into(), b"identity mismatch");
+      return IdentityMismatchError { actual, peer }.fail();
+    }
 
-    self.local.write().await.insert(peer.id, peer);
+    self.clone().spawn_serve(connection.clone(), peer);
 
-    Ok(())
+    Ok(connection)
   }
 
-  async fn find(&self, id: Id) -> Option<Peer> {
-    self.local.read().await.get(&id).copied()
+  // stream frames sent and received, which do not include keep-alives
+  fn activity(connection: &Connection) -> u64 {
+    let stats = connection.stats();
+    stats.frame_rx.stream + stats.frame_tx.stream
   }
 
-  async fn check(&self, peer: Peer) -> Result {
-    let connection = self
-      .endpoint
-      .connect(peer.socket_addr(), &peer.id.to_string())
-      .context(ConnectError { peer })?
-      .await
-      .context(ConnectionError { peer })?;
-
-    assert_eq!(passthrough::Session::peer_identity(&connection), peer.id);
-
-    let (mut tx, rx) = connection
-      .open_bi()
-      .await
-      .context(ConnectionError { peer })?;
+  // not async, so that the futures of `dial` and `serve` do not
+  // depend on each other
+  fn spawn_serve(self: Arc<Self>, connection: Connection, peer: Peer) {
+    let member = passthrough::Session::is_member(&connection);
 
-    self.send(peer, &mut tx, Message::Ping).await?;
+    tokio::spawn(async move {
+      if let Err(err) = self.serve(connection, peer, member, true).await {
+        err.report();
+      }
+    });
+  }
 
-    assert!(matches!(self.receive(peer, rx).await?, response::Ping));
+  async fn punch(self: &Arc<Self>, peer: Peer) -> Option<Connection> {
+    let rendezvous = self
+      .pool
+      .connections()
+      .into_iter()
+      .filter(|(id, _connection)| *id != peer.id)
+      .take(Self::MAX_RENDEZVOUS_ATTEMPTS);
 
-    Self::finish(connection, peer, Status::Done, tx).await?;
+    for (id, connection) in rendezvous {
+      let rendezvous = Peer::new(id, connection.remote_address());
 
-    Ok(())
-  }
+      let introduced = match self
+        .request_on(rendezvous, &connection, Message::Introduce(peer.id))
+        .await
+      {
+        Ok((response::Introduce(Some(introduced)), _rx)) => introduced,
+        Ok((response::Introduce(None), _rx)) => continue,
+        Err(err) => {
+          log::debug!("failed to request introduction to {peer} from {rendezvous}: {err}");
+          continue;
+        }
+      };
 
-  async fn finish(
-    connection: Connection,
-    peer: Peer,
-    status: Status,
-    mut tx: SendStream,
-  ) -> Result {
-    tx.stopped().await.context(StopError { peer })?;
+      log::info!("punching hole to {introduced} via {rendezvous}");
 
-    connection.close(status.error_code(), &status.to_cbor());
+      match self.dial(introduced, false).await {
+        Ok(connection) => return Some(connection),
+        Err(err) => log::debug!("failed to connect to {introduced} via {rendezvous}: {err}"),
+      }
+    }
 
-    Ok(())
+    None
   }
 
-  pub(crate) async fn get(&self, id: Id, package: Hash) -> Result<Option<Manifest>> {
-    let Some(peer) = self.find(id).await else {
-      return Ok(None);
-    };
+  async fn introduce(&self, peer: Peer, id: Id) -> Option<Peer> {
+    if id == peer.id || id == self.id {
+      return None;
+    }
 
-    let connection = self
-      .endpoint
-      .connect(peer.socket_addr(), &peer.id.to_string())
-      .context(ConnectError { peer })?
-      .await
-      .context(ConnectionError { peer })?;
+    let connection = self.pool.reachable(id)?;
 
-    assert_eq!(passthrough::Session::peer_identity(&connection), peer.id);
+    let target = Peer::new(id, connection.remote_address());
 
-    let (mut tx, rx) = connection
-      .open_bi()
+    match self
+      .request_on::<response::Punch>(target, &connection, Message::Punch(peer))
       .await
-      .context(ConnectionError { peer })?;
-
-    self.send(peer, &mut tx, Message::Get(package)).await?;
+    {
+      Ok(_) => {
+        log::debug!("introduced {peer} to {target}");
+        Some(target)
+      }
+      Err(err) => {
+        log::debug!("failed to introduce {peer} to {target}: {err}");
+        None
+      }
+    }
+  }
 
-    let response::Get(file) = self.receive(peer, rx).await?;
+  async fn punched(self: Arc<Self>, peer: Peer) {
+    if peer.id == self.id || self.pool.connection(peer.id).is_some() {
+      return;
+    }
 
-    Self::finish(connection, peer, Status::Done, tx).await?;
+    log::debug!("punching hole to {peer}");
 
-    Ok(file.map(|file| Manifest::from_cbor(&file).unwrap()))
-  }
+    match self.dial(peer, false).await {
+      Ok(connection) => {
+        self.pool.insert(peer.id, connection);
 
-  pub(crate) async fn search(&self, id: Id) -> Result<Option<Vec<Hash>>> {
-    let Some((_id, &peer)) = self
-      .local
+        if let Err(err) = self.ping(peer).await {
+          log::debug!("failed to ping {peer} after punching hole: {err}");
+        }
+      }
+      Err(err) => log::debug!("failed to punch hole to {peer}: {err}"),
+    }
+  }
+
+  async fn open(self: &Arc<Self>, peer: Peer) -> Result<(SendStream, RecvStream)> {
+    let relay = self.relayed.read().await.get(&peer.id).copied();
+
+    if let Some(relay) = relay {
+      match self.open_relayed(relay, peer).await {
+        Ok(stream) => return Ok(stream),
+        Err(err) => {
+          log::info!("no longer relaying to {peer} via {relay}: {err}");
+          self.relayed.write().await.remove(&peer.id);
+        }
+      }
+    }
+
+    let err = match self.open_direct(peer).await {
+      Err(err) if err.is_timeout() => err,
+      result => return result,
+    };
+
+    let relays = self
+      .pool
+      .connections()
+      .into_iter()
+      .filter(|(id, _connection)| *id != peer.id)
+      .take(Self::MAX_RENDEZVOUS_ATTEMPTS);
+
+    for (id, connection) in relays {
+      let relay = Peer::new(id, connection.remote_address());
+
+      match self.open_relayed(relay, peer).await {
+        Ok(stream) => {
+          log::info!("relaying to {peer} via {relay}");
+          self.relayed.write().await.insert(peer.id, relay);
+          return Ok(stream);
+        }
+        Err(err) => log::debug!("failed to relay to {peer} via {relay}: {err}"),
+      }
+    }
+
+    Err(err)
+  }
+
+  async fn open_relayed(
+    self: &Arc<Self>,
+    relay: Peer,
+    peer: Peer,
+  ) -> Result<(SendStream, RecvStream)> {
+    let connection = self.pool.get(relay.id, || self.dial(relay, true)).await?;
+
+    tokio::time::timeout(Self::REQUEST_TIMEOUT, async {
+      let (mut tx, mut rx) = connection
+        .open_bi()
+        .await
+        .context(ConnectionError { peer: relay })?;
+
+      self.write(relay, &mut tx, Message::Relay(peer.id)).await?;
+
+      let response::Relay(accepted) = self.read(relay, &mut rx).await?;
+
+      ensure!(accepted, RelayRefusedError { peer, relay });
+
+      Ok((tx, rx))
+    })
+    .await
+    .map_err(|_| RequestTimeoutError { peer: relay }.build())?
+  }
+
+  async fn open_direct(self: &Arc<Self>, peer: Peer) -> Result<(SendStream, RecvStream)> {
+    let connection = self.pool.get(peer.id, || self.connect(peer)).await?;
+
+    match connection.open_bi().await {
+      Ok(stream) => Ok(stream),
+      Err(err) => {
+        log::debug!("reconnecting to {peer}: {err}");
+
+        self.pool.remove(peer.id, &connection);
+
+        self
+          .pool
+          .get(peer.id, || self.connect(peer))
+          .await?
+          .open_bi()
+          .await
+          .context(ConnectionError { peer })
+      }
+    }
+  }
+
+  async fn request<T: DeserializeOwned>(
+    self: &Arc<Self>,
+    peer: Peer,
+    message: Message,
+  ) -> Result<(T, RecvStream)> {
+    // opening may dial, punch, and try relays, so it shares the deadline
+    tokio::time::timeout(Self::REQUEST_TIMEOUT, async {
+      let (tx, rx) = self.open(peer).await?;
+      self.exchange(peer, tx, rx, message).await
+    })
+    .await
+    .map_err(|_| RequestTimeoutError { peer }.build())?
+  }
+
+  async fn request_on<T: DeserializeOwned>(
+    &self,
+    peer: Peer,
+    connection: &Connection,
+    message: Message,
+  ) -> Result<(T, RecvStream)> {
+    tokio::time::timeout(Self::REQUEST_TIMEOUT, async {
+      let (tx, rx) = connection
+        .open_bi()
+        .await
+        .context(ConnectionError { peer })?;
+      self.exchange(peer, tx, rx, message).await
+    })
+    .await
+    .map_err(|_| RequestTimeoutError { peer }.build())?
+  }
+
+  async fn exchange<T: DeserializeOwned>(
+    &self,
+    peer: Peer,
+    mut tx: SendStream,
+    mut rx: RecvStream,
+    message: Message,
+  ) -> Result<(T, RecvStream)> {
+    let name: &'static str = (&message).into();
+
+    self.send(peer, &mut tx, message).await?;
+
+    let response = match self.read(peer, &mut rx).await {
+      Err(Error::Deserialize {
+        source: ciborium::de::Error::Semantic(..),
+        ..
+      }) => {
+        return UnexpectedResponseError {
+          message: name,
+          peer,
+        }
+        .fail()
+      }
+      result => result?,
+    };
+
+    Ok((response, rx))
+  }
+
+  async fn retry<T, F, Fut>(&self, peer: Peer, mut f: F) -> Result<T>
+  where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T>>,
+  {
+    let mut delay = Self::RETRY_DELAY;
+
+    for attempt in 1.. {
+      match f().await {
+        Err(err) if attempt < Self::MAX_ATTEMPTS && err.is_transient() => {
+          log::debug!("retrying request to {peer} after attempt {attempt} failed: {err}");
+          tokio::time::sleep(delay).await;
+          delay *= 2;
+        }
+        result => return result,
+      }
+    }
+
+    unreachable!()
+  }
+
+  pub(crate) fn pool_stats(&self) -> pool::Stats {
+    self.pool.stats()
+  }
+
+  pub(crate) fn limits(&self) -> Limits {
+    self.limits
+  }
+
+  pub(crate) fn incoming(&self) -> u64 {
+    (self.limits.max_connections - self.connections.available_permits()).into_u64()
+  }
+
+  pub(crate) fn upload(&self) -> &Throttle {
+    &self.upload
+  }
+
+  pub(crate) fn relay_stats(&self) -> Option<relay::Stats> {
+    self.relay.as_ref().map(|relay| relay.stats())
+  }
+
+  pub(crate) async fn relayed(&self) -> BTreeMap<Id, Peer> {
+    self
+      .relayed
       .read()
       .await
       .iter()
-      .find(|(_id, peer)| peer.id == id)
-    else {
+      .map(|(id, relay)| (*id, *relay))
+      .collect()
+  }
+
+  pub(crate) async fn ping(self: &Arc<Self>, peer: Peer) -> Result {
+    log::debug!("pinging {peer}");
+
+    let rtt = self.check(peer).await?;
+
+    let peer = self.pool.connection(peer.id).map_or(peer, |connection| {
+      Peer::new(peer.id, connection.remote_address())
+    });
+
+    match self.request(peer, Message::Observe).await {
+      Ok((response::Observe(address), _rx)) => {
+        self.observed.write().await.insert(peer.id, address);
+      }
+      Err(err) => log::debug!("failed to learn observed address from {peer}: {err}"),
+    }
+
+    self
+      .local
+      .write()
+      .await
+      .insert(peer.id, Contact::new(peer, rtt));
+
+    Ok(())
+  }
+
+  pub(crate) async fn bootstrap(self: &Arc<Self>, peers: &[Bootstrap]) {
+    if peers.is_empty() {
+      return;
+    }
+
+    let mut delay = Self::BOOTSTRAP_RETRY_DELAY;
+
+    loop {
+      let mut tasks = JoinSet::new();
+
+      for bootstrap in peers {
+        let bootstrap = bootstrap.clone();
+        let node = self.clone();
+        tasks.spawn(async move {
+          let result = match bootstrap.resolve().await.context(BootstrapError) {
+            Ok(peer) => node.rendezvous(peer).await,
+            Err(err) => Err(err),
+          };
+          (bootstrap, result)
+        });
+      }
+
+      let mut reached = 0;
+
+      while let Some(result) = tasks.join_next().await {
+        match result.unwrap() {
+          (_bootstrap, Ok(())) => reached += 1,
+          (bootstrap, Err(err)) => log::warn!("failed to bootstrap from {bootstrap}: {err}"),
+        }
+      }
+
+      if reached > 0 {
+        log::info!("bootstrapped from {reached} of {} peers", peers.len());
+        return;
+      }
+
+      log::warn!("failed to reach any bootstrap peers, retrying in {delay:?}");
+
+      tokio::time::sleep(delay).await;
+
+      delay = (delay * 2).min(Self::MAX_BOOTSTRAP_RETRY_DELAY);
+    }
+  }
+
+  // bootstrap peers are used as rendezvous peers, so keep connections to
+  // them alive
+  async fn rendezvous(self: &Arc<Self>, peer: Peer) -> Result {
+    self.pool.get(peer.id, || self.dial(peer, true)).await?;
+    self.ping(peer).await
+  }
+
+  async fn find(&self, id: Id) -> Option<Peer> {
+    self.local.read().await.get(&id).map(|contact| contact.peer)
+  }
+
+  pub(crate) async fn public_address(&self) -> Option<SocketAddr> {
+    let mut reports = BTreeMap::<SocketAddr, usize>::new();
+
+    for address in self.observed.read().await.values() {
+      *reports.entry(*address).or_default() += 1;
+    }
+
+    reports
+      .into_iter()
+      .max_by_key(|(_address, reports)| *reports)
+      .map(|(address, _reports)| address)
+  }
+
+  async fn check(self: &Arc<Self>, peer: Peer) -> Result<Duration> {
+    let start = Instant::now();
+
+    let (response::Ping, _rx) = self
+      .retry(peer, || self.request(peer, Message::Ping))
+      .await?;
+
+    Ok(start.elapsed())
+  }
+
+  async fn monitor(self: Arc<Self>) {
+    loop {
+      tokio::time::sleep(Self::HEALTH_CHECK_INTERVAL).await;
+
+      self.check_health().await;
+
+      if let Err(err) = self.save_peers().await {
+        err.report();
+      }
+    }
+  }
+
+  pub(crate) async fn save_peers(&self) -> Result {
+    let Some(path) = &self.peer_table else {
+      return Ok(());
+    };
+
+    let peers = self
+      .local
+      .read()
+      .await
+      .values()
+      .map(|contact| contact.peer)
+      .collect::<BTreeSet<Peer>>();
+
+    let tmp = path.with_extension("json.tmp");
+
+    if let Some(parent) = path.parent() {
+      tokio::fs::create_dir_all(parent)
+        .await
+        .context(PeerTableWriteError { path })?;
+    }
+
+    tokio::fs::write(&tmp, serde_json::to_vec_pretty(&peers).unwrap())
+      .await
+      .context(PeerTableWriteError { path })?;
+
+    tokio::fs::rename(&tmp, path)
+      .await
+      .context(PeerTableWriteError { path })?;
+
+    Ok(())
+  }
+
+  async fn load_peers(&self) -> Result<Vec<Peer>> {
+    let Some(path) = &self.peer_table else {
+      return Ok(Vec::new());
+    };
+
+    let json = match tokio::fs::read(path).await {
+      Ok(json) => json,
+      Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+      Err(err) => return Err(err).context(PeerTableReadError { path }),
+    };
+
+    serde_json::from_slice(&json).context(PeerTableDeserializeError { path })
+  }
+
+  pub(crate) async fn restore_peers(self: &Arc<Self>) -> Result {
+    let peers = self.load_peers().await?;
+
+    if peers.is_empty() {
+      return Ok(());
+    }
+
+    log::info!("revalidating {} saved peers", peers.len());
+
+    let mut tasks = JoinSet::new();
+
+    for peer in peers {
+      let node = self.clone();
+      tasks.spawn(async move { (peer, node.ping(peer).await) });
+    }
+
+    while let Some(result) = tasks.join_next().await {
+      if let (peer, Err(err)) = result.unwrap() {
+        log::info!("dropping saved peer {peer}: {err}");
+      }
+    }
+
+    Ok(())
+  }
+
+  async fn check_health(self: &Arc<Self>) {
+    let mut tasks = JoinSet::new();
+
+    for contact in self.local.read().await.values() {
+      let node = self.clone();
+      let peer = contact.peer;
+      tasks.spawn(async move { (peer, node.check(peer).await) });
+    }
+
+    while let Some(result) = tasks.join_next().await {
+      let (peer, result) = result.unwrap();
+
+      let mut local = self.local.write().await;
+
+      let Some(contact) = local.get_mut(&peer.id) else {
+        continue;
+      };
+
+      match result {
+        Ok(rtt) => *contact = Contact::new(peer, rtt),
+        Err(err) => {
+          contact.failures += 1;
+
+          log::warn!("health check {} of {peer} failed: {err}", contact.failures,);
+
+          if contact.failures >= Self::MAX_FAILURES {
+            log::info!("evicting unresponsive peer {peer}");
+            local.remove(&peer.id);
+            self.observed.write().await.remove(&peer.id);
+            self.remote.write().await.remove(&peer.id);
+          }
+        }
+      }
+    }
+  }
+
+  pub(crate) async fn get(self: &Arc<Self>, id: Id, package: Hash) -> Result<Option<Manifest>> {
+    let Some(peer) = self.find(id).await else {
+      return Ok(None);
+    };
+
+    if let Some(manifest) = self.cache.get(package).await {
+      self.index_remote(id, package, manifest.clone()).await;
+      return Ok(Some(manifest));
+    }
+
+    let (response::Get(file), _rx) = self
+      .retry(peer, || self.request(peer, Message::Get(package)))
+      .await?;
+
+    let Some(file) = file else {
+      return Ok(None);
+    };
+
+    let actual = Hash::bytes(&file);
+
+    ensure!(
+      actual == package,
+      ManifestHashError {
+        actual,
+        expected: package,
+        peer,
+      }
+    );
+
+    let manifest = Manifest::from_cbor(&file).context(InvalidManifestError {
+      hash: package,
+      peer,
+    })?;
+
+    self.cache.insert(package, &file, manifest.clone()).await;
+
+    self.index_remote(id, package, manifest.clone()).await;
+
+    Ok(Some(manifest))
+  }
+
+  pub(crate) async fn file(self: &Arc<Self>, id: Id, hash: Hash) -> Result<Option<Vec<u8>>> {
+    let Some(peer) = self.find(id).await else {
+      return Ok(None);
+    };
+
+    let (response::File(len), mut rx) = self.request(peer, Message::File(hash)).await?;
+
+    let Some(len) = len else {
       return Ok(None);
     };
 
-    let connection = self.connect(peer).await?;
+    ensure!(len <= Self::MAX_FILE_LEN, FileLengthError { len, peer });
+
+    // grow the buffer as chunks arrive, so a peer can't make us allocate
+    // the length it claims without sending it
+    let mut content =
+      Vec::with_capacity(len.min(throttle::CHUNK_LEN.into_u64()).try_into().unwrap());
+
+    while content.len().into_u64() < len {
+      let start = content.len();
+
+      let chunk = (len - start.into_u64())
+        .min(throttle::CHUNK_LEN.into_u64())
+        .try_into()
+        .unwrap();
+
+      self.download.acquire(peer.ip, chunk).await;
+
+      content.resize(start + chunk, 0);
+
+      tokio::time::timeout(Self::FILE_TIMEOUT, rx.read_exact(&mut content[start..]))
+        .await
+        .map_err(|_| RequestTimeoutError { peer }.build())?
+        .context(ReadError { peer })?;
+    }
+
+    let actual = Hash::bytes(&content);
+
+    ensure!(
+      actual == hash,
+      FileHashError {
+        actual,
+        expected: hash,
+        peer,
+      }
+    );
+
+    Ok(Some(content))
+  }
 
-    let (mut tx, rx) = connection
-      .open_bi()
+  async fn index_remote(&self, id: Id, hash: Hash, manifest: Manifest) {
+    self
+      .remote
+      .write()
       .await
-      .context(ConnectionError { peer })?;
+      .entry(id)
+      .or_default()
+      .insert(hash, manifest);
+  }
+
+  pub(crate) async fn query(self: &Arc<Self>, id: Id, query: &str) -> Result<Option<Vec<Hash>>> {
+    let Some(peer) = self.find(id).await else {
+      return Ok(None);
+    };
+
+    let (response::Query(results), _rx) = self.request(peer, Message::Query(query.into())).await?;
 
-    self.send(peer, &mut tx, Message::Search).await?;
+    Ok(Some(results))
+  }
 
-    let response::Search(results) = self.receive(peer, rx).await?;
+  pub(crate) async fn search(self: &Arc<Self>, id: Id) -> Result<Option<Vec<Hash>>> {
+    let Some(peer) = self.find(id).await else {
+      return Ok(None);
+    };
 
-    Self::finish(connection, peer, Status::Done, tx).await?;
+    let (response::Search(results), _rx) = self
+      .retry(peer, || self.request(peer, Message::Search))
+      .await?;
 
     Ok(Some(results))
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use {
+    super::*,
+    tokio::{runtime::Runtime, task::JoinHandle},
+  };
+
+  fn fake_peer(
+    id: Id,
+    respond: impl Fn(Message) -> Vec<u8> + Send + 'static,
+  ) -> (Peer, JoinHandle<()>) {
+    let endpoint = passthrough::Session::endpoint(
+      id,
+      None,
+      Ipv4Addr::LOCALHOST.into(),
+      0,
+      limits::DEFAULT_MAX_STREAMS,
+    );
+
+    let peer = Peer::new(id, endpoint.local_addr().unwrap());
+
+    let server = tokio::spawn(async move {
+      while let Some(incoming) = endpoint.accept().await {
+        let Ok(connection) = incoming.await else {
+          continue;
+        };
+
+        while let Ok((mut tx, mut rx)) = connection.accept_bi().await {
+          let mut len = [0; 2];
+          rx.read_exact(&mut len).await.unwrap();
+
+          let mut message = vec![0; u16::from_le_bytes(len).into()];
+          rx.read_exact(&mut message).await.unwrap();
+
+          let response = respond(Message::from_cbor(&message).unwrap());
+
+          tx.write_all(&u16::try_from(response.len()).unwrap().to_le_bytes())
+            .await
+            .unwrap();
+          tx.write_all(&response).await.unwrap();
+          tx.finish().unwrap();
+        }
+      }
+    });
+
+    (peer, server)
+  }
+
+  #[test]
+  fn identity_mismatch() {
+    Runtime::new().unwrap().block_on(async {
+      let node = Arc::new(
+        Node::new(
+          Ipv4Addr::LOCALHOST.into(),
+          None,
+          Limits::default(),
+          None,
+          BTreeMap::new(),
+          0,
+          None,
+        )
+        .await
+        .unwrap(),
+      );
+
+      let (impostor, server) = fake_peer(random_id(), |_message| response::Ping.to_cbor());
+
+      let peer = Peer {
+        id: random_id(),
+        ..impostor
+      };
+
+      assert_matches!(
+        node.ping(peer).await,
+        Err(Error::IdentityMismatch { actual, peer: expected, .. })
+        if actual == impostor.id && expected == peer,
+      );
+
+      assert!(node.local.read().await.is_empty());
+
+      assert_eq!(node.pool_stats().connections, 0);
+
+      server.abort();
+    });
+  }
+
+  #[test]
+  fn unexpected_response() {
+    Runtime::new().unwrap().block_on(async {
+      let node = Arc::new(
+        Node::new(
+          Ipv4Addr::LOCALHOST.into(),
+          None,
+          Limits::default(),
+          None,
+          BTreeMap::new(),
+          0,
+          None,
+        )
+        .await
+        .unwrap(),
+      );
+
+      let (peer, server) = fake_peer(random_id(), |_message| {
+        response::Search(vec![Hash::bytes(b"foo")]).to_cbor()
+      });
+
+      assert_matches!(
+        node.ping(peer).await,
+        Err(Error::UnexpectedResponse {
+          message: "Ping",
+          ..
+        }),
+      );
+
+      assert!(node.local.read().await.is_empty());
+
+      server.abort();
+    });
+  }
+
+  #[test]
+  fn malformed_response() {
+    Runtime::new().unwrap().block_on(async {
+      let node = Arc::new(
+        Node::new(
+          Ipv4Addr::LOCALHOST.into(),
+          None,
+          Limits::default(),
+          None,
+          BTreeMap::new(),
+          0,
+          None,
+        )
+        .await
+        .unwrap(),
+      );
+
+      let (peer, server) = fake_peer(random_id(), |_message| vec![0x1c]);
+
+      assert_matches!(node.ping(peer).await, Err(Error::Deserialize { .. }));
+
+      server.abort();
+    });
+  }
+
+  #[test]
+  fn invalid_manifest() {
+    Runtime::new().unwrap().block_on(async {
+      let node = Arc::new(
+        Node::new(
+          Ipv4Addr::LOCALHOST.into(),
+          None,
+          Limits::default(),
+          None,
+          BTreeMap::new(),
+          0,
+          None,
+        )
+        .await
+        .unwrap(),
+      );
+
+      let manifest = "not a manifest".to_cbor();
+
+      let hash = Hash::bytes(&manifest);
+
+      let (peer, server) = fake_peer(random_id(), move |message| match message {
+        Message::Get(_) => response::Get(Some(manifest.clone())).to_cbor(),
+        _ => response::Ping.to_cbor(),
+      });
+
+      node.ping(peer).await.unwrap();
+
+      assert_matches!(
+        node.get(peer.id, hash).await,
+        Err(Error::InvalidManifest { hash: actual, peer: actual_peer, .. })
+        if actual == hash && actual_peer == peer,
+      );
+
+      assert!(node.query_remote("").await.is_empty());
+
+      server.abort();
+    });
+  }
+
+  #[test]
+  fn oversized_message() {
+    Runtime::new().unwrap().block_on(async {
+      let node = Arc::new(
+        Node::new(
+          Ipv4Addr::LOCALHOST.into(),
+          None,
+          Limits::default(),
+          None,
+          BTreeMap::new(),
+          0,
+          None,
+        )
+        .await
+        .unwrap(),
+      );
+
+      let (peer, server) = fake_peer(random_id(), |_message| response::Ping.to_cbor());
+
+      node.ping(peer).await.unwrap();
+
+      assert_matches!(
+        node.query(peer.id, &"a".repeat(u16::MAX.into())).await,
+        Err(Error::MessageLength { len, .. }) if len > u16::MAX.into(),
+      );
+
+      server.abort();
+    });
+  }
+
+  #[test]
+  fn truncated_file() {
+    Runtime::new().unwrap().block_on(async {
+      let node = Arc::new(
+        Node::new(
+          Ipv4Addr::LOCALHOST.into(),
+          None,
+          Limits::default(),
+          None,
+          BTreeMap::new(),
+          0,
+          None,
+        )
+        .await
+        .unwrap(),
+      );
+
+      let (peer, server) = fake_peer(random_id(), |message| match message {
+        Message::File(_) => response::File(Some(Node::MAX_FILE_LEN)).to_cbor(),
+        _ => response::Ping.to_cbor(),
+      });
+
+      node.ping(peer).await.unwrap();
+
+      assert_matches!(
+        node.file(peer.id, Hash::bytes(b"foo")).await,
+        Err(Error::Read { .. }),
+      );
+
+      server.abort();
+    });
+  }
+
+  #[test]
+  fn spoofed_inbound_ids_are_not_pooled() {
+    Runtime::new().unwrap().block_on(async {
+      let node = Arc::new(
+        Node::new(
+          Ipv4Addr::LOCALHOST.into(),
+          None,
+          Limits::default(),
+          None,
+          BTreeMap::new(),
+          0,
+          None,
+        )
+        .await
+        .unwrap(),
+      );
+
+      let server = {
+        let node = node.clone();
+        tokio::spawn(async move {
+          while let Some(incoming) = node.endpoint.accept().await {
+            tokio::spawn(node.clone().accept(incoming));
+          }
+        })
+      };
+
+      let (peer, fake) = fake_peer(random_id(), |_message| response::Ping.to_cbor());
+
+      let spoofer = passthrough::Session::endpoint(
+        peer.id,
+        None,
+        Ipv4Addr::LOCALHOST.into(),
+        0,
+        limits::DEFAULT_MAX_STREAMS,
+      );
+
+      let spoofed = spoofer
+        .connect(node.peer().socket_addr(), &node.id.to_string())
+        .unwrap()
+        .await
+        .unwrap();
+
+      for _ in 0..100 {
+        if node.pool.reachable(peer.id).is_some() {
+          break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+      }
+
+      assert_eq!(
+        node.pool.reachable(peer.id).unwrap().remote_address(),
+        spoofer.local_addr().unwrap(),
+      );
+
+      assert!(node.pool.connection(peer.id).is_none());
+
+      node.ping(peer).await.unwrap();
+
+      assert_eq!(
+        node.pool.connection(peer.id).unwrap().remote_address(),
+        peer.socket_addr(),
+      );
+
+      assert_eq!(node.pool.stats().opened, 1);
+
+      spoofed.close(0u32.into(), b"");
+      fake.abort();
+      server.abort();
+    });
+  }
+
+  #[test]
+  fn unresponsive_peer_times_out() {
+    Runtime::new().unwrap().block_on(async {
+      let node = Arc::new(
+        Node::new(
+          Ipv4Addr::LOCALHOST.into(),
+          None,
+          Limits::default(),
+          None,
+          BTreeMap::new(),
+          0,
+          None,
+        )
+        .await
+        .unwrap(),
+      );
+
+      let id = random_id();
+
+      let endpoint = passthrough::Session::endpoint(
+        id,
+        None,
+        Ipv4Addr::LOCALHOST.into(),
+        0,
+        limits::DEFAULT_MAX_STREAMS,
+      );
+
+      let peer = Peer::new(id, endpoint.local_addr().unwrap());
+
+      let server = tokio::spawn(async move {
+        let mut connections = Vec::new();
+        while let Some(incoming) = endpoint.accept().await {
+          connections.push(incoming.await.unwrap());
+        }
+      });
+
+      node
+        .local
+        .write()
+        .await
+        .insert(id, Contact::new(peer, Duration::ZERO));
+
+      let start = std::time::Instant::now();
+
+      assert_matches!(
+        node.search(id).await,
+        Err(Error::RequestTimeout { peer: actual, .. }) if actual == peer,
+      );
+
+      assert!(start.elapsed() >= Node::REQUEST_TIMEOUT * Node::MAX_ATTEMPTS);
+
+      assert_eq!(node.pool_stats().opened, 1);
+
+      assert_matches!(
+        node.query(id, "foo").await,
+        Err(Error::RequestTimeout { .. }),
+      );
+
+      server.abort();
+    });
+  }
+
+  #[test]
+  fn request_deadline_includes_open() {
+    Runtime::new().unwrap().block_on(async {
+      let node = Arc::new(
+        Node::new(
+          Ipv4Addr::LOCALHOST.into(),
+          None,
+          Limits::default(),
+          None,
+          BTreeMap::new(),
+          0,
+          None,
+        )
+        .await
+        .unwrap(),
+      );
+
+      let mut servers = Vec::new();
+
+      // pooled peers that never answer stall both punching and relaying
+      for _ in 0..2 {
+        let id = random_id();
+
+        let endpoint = passthrough::Session::endpoint(
+          id,
+          None,
+          Ipv4Addr::LOCALHOST.into(),
+          0,
+          limits::DEFAULT_MAX_STREAMS,
+        );
+
+        let peer = Peer::new(id, endpoint.local_addr().unwrap());
+
+        servers.push(tokio::spawn(async move {
+          let mut connections = Vec::new();
+          while let Some(incoming) = endpoint.accept().await {
+            connections.push(incoming.await.unwrap());
+          }
+        }));
+
+        node.pool.get(id, || node.dial(peer, false)).await.unwrap();
+      }
+
+      let socket = std::net::UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+
+      let peer = Peer::new(random_id(), socket.local_addr().unwrap());
+
+      let start = std::time::Instant::now();
+
+      assert_matches!(
+        node.request::<response::Ping>(peer, Message::Ping).await.err(),
+        Some(Error::RequestTimeout { peer: actual, .. }) if actual == peer,
+      );
+
+      assert!(start.elapsed() < Node::REQUEST_TIMEOUT * 2);
+
+      for server in servers {
+        server.abort();
+      }
+    });
+  }
+
+  #[test]
+  fn missing_peer_times_out() {
+    Runtime::new().unwrap().block_on(async {
+      let node = Arc::new(
+        Node::new(
+          Ipv4Addr::LOCALHOST.into(),
+          None,
+          Limits::default(),
+          None,
+          BTreeMap::new(),
+          0,
+          None,
+        )
+        .await
+        .unwrap(),
+      );
+
+      let socket = std::net::UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+
+      let peer = Peer::new(random_id(), socket.local_addr().unwrap());
+
+      let err = node.ping(peer).await.unwrap_err();
+
+      assert!(err.is_timeout());
+
+      assert_matches!(err, Error::ConnectTimeout { .. });
+
+      assert!(node.local.read().await.is_empty());
+    });
+  }
+
+  #[test]
+  fn holes_are_punched_through_rendezvous() {
+    Runtime::new().unwrap().block_on(async {
+      let mut nodes = Vec::new();
+      let mut servers = Vec::new();
+
+      for _ in 0..3 {
+        let node = Arc::new(
+          Node::new(
+            Ipv4Addr::LOCALHOST.into(),
+            None,
+            Limits::default(),
+            None,
+            BTreeMap::new(),
+            0,
+            None,
+          )
+          .await
+          .unwrap(),
+        );
+
+        servers.push({
+          let node = node.clone();
+          tokio::spawn(async move {
+            while let Some(incoming) = node.endpoint.accept().await {
+              tokio::spawn(node.clone().accept(incoming));
+            }
+          })
+        });
+
+        nodes.push(node);
+      }
+
+      let [rendezvous, a, b] = nodes.try_into().ok().unwrap();
+
+      a.ping(rendezvous.peer()).await.unwrap();
+      b.ping(rendezvous.peer()).await.unwrap();
+
+      assert_eq!(
+        a.public_address().await,
+        Some(a.endpoint.local_addr().unwrap()),
+      );
+
+      let unreachable = std::net::UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+
+      a.ping(Peer::new(b.id, unreachable.local_addr().unwrap()))
+        .await
+        .unwrap();
+
+      assert_eq!(a.local.read().await[&b.id].peer, b.peer());
+
+      for _ in 0..100 {
+        if b.local.read().await.contains_key(&a.id) {
+          break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+      }
+
+      assert_eq!(b.local.read().await[&a.id].peer, a.peer());
+
+      for server in servers {
+        server.abort();
+      }
+    });
+  }
+
+  #[test]
+  fn unreachable_peers_are_relayed() {
+    Runtime::new().unwrap().block_on(async {
+      let tempdir = tempdir();
+
+      let output = tempdir.join("comic.package");
+
+      subcommand::package::Package {
+        compress: false,
+        optimize: Default::default(),
+        root: "tests/packages/comic".into(),
+        output: output.clone(),
+        thumbnails: false,
+      }
+      .run()
+      .unwrap();
+
+      let package = Arc::new(Package::load(&output).unwrap());
+
+      // the origin a relay claims is only trusted for visibility when the
+      // relay is a member of the network
+      for network_key in [None, Some("foo")] {
+        relay(&package, network_key.map(|key| key.parse().unwrap())).await;
+      }
+    });
+
+    async fn relay(package: &Arc<Package>, network_key: Option<NetworkKey>) {
+      let mut servers = Vec::new();
+
+      // `a` and `b` use different address families, so they can only
+      // reach each other through the dual-stack relay
+      let mut nodes = Vec::new();
+
+      for (address, relay) in [
+        (
+          IpAddr::from(Ipv6Addr::UNSPECIFIED),
+          Some(relay::Config {
+            bandwidth: None,
+            max_streams: 1,
+          }),
+        ),
+        (Ipv4Addr::LOCALHOST.into(), None),
+        (Ipv6Addr::LOCALHOST.into(), None),
+      ] {
+        let node = Arc::new(
+          Node::new(
+            address,
+            None,
+            Limits::default(),
+            network_key,
+            BTreeMap::new(),
+            0,
+            relay,
+          )
+          .await
+          .unwrap(),
+        );
+
+        servers.push({
+          let node = node.clone();
+          tokio::spawn(async move {
+            while let Some(incoming) = node.endpoint.accept().await {
+              tokio::spawn(node.clone().accept(incoming));
+            }
+          })
+        });
+
+        nodes.push(node);
+      }
+
+      let [relay, a, b] = nodes.try_into().ok().unwrap();
+
+      b.set_packages(
+        [(package.hash, package.clone())].into(),
+        BTreeSet::new(),
+        [(package.hash, Visibility::Allow([a.id].into()))].into(),
+      )
+      .await;
+
+      a.ping(Peer::new(
+        relay.id,
+        (Ipv4Addr::LOCALHOST, relay.port).into(),
+      ))
+      .await
+      .unwrap();
+
+      b.ping(Peer::new(
+        relay.id,
+        (Ipv6Addr::LOCALHOST, relay.port).into(),
+      ))
+      .await
+      .unwrap();
+
+      let unreachable = std::net::UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+
+      a.ping(Peer::new(b.id, unreachable.local_addr().unwrap()))
+        .await
+        .unwrap();
+
+      assert_eq!(a.relayed().await.keys().collect::<Vec<&Id>>(), [&b.id],);
+
+      if network_key.is_some() {
+        assert_eq!(a.search(b.id).await.unwrap(), Some(vec![package.hash]));
+
+        assert_eq!(
+          a.get(b.id, package.hash).await.unwrap(),
+          Some(package.manifest.clone()),
+        );
+      } else {
+        assert_eq!(a.search(b.id).await.unwrap(), Some(Vec::new()));
+
+        assert_eq!(a.get(b.id, package.hash).await.unwrap(), None);
+      }
+
+      let stats = relay.relay_stats().unwrap();
+
+      assert!(stats.bytes > 0);
+
+      b.ping(Peer::new(a.id, unreachable.local_addr().unwrap()))
+        .await
+        .unwrap();
+
+      assert_eq!(b.relayed().await[&a.id].id, relay.id);
+
+      for server in servers {
+        server.abort();
+      }
+    }
+  }
+
+  #[test]
+  fn relayed_requests_on_inbound_connections_are_refused() {
+    Runtime::new().unwrap().block_on(async {
+      let tempdir = tempdir();
+
+      let output = tempdir.join("comic.package");
+
+      subcommand::package::Package {
+        compress: false,
+        optimize: Default::default(),
+        root: "tests/packages/comic".into(),
+        output: output.clone(),
+        thumbnails: false,
+      }
+      .run()
+      .unwrap();
+
+      let package = Arc::new(Package::load(&output).unwrap());
+
+      let node = Arc::new(
+        Node::new(
+          Ipv4Addr::LOCALHOST.into(),
+          None,
+          Limits::default(),
+          None,
+          BTreeMap::new(),
+          0,
+          None,
+        )
+        .await
+        .unwrap(),
+      );
+
+      let server = tokio::spawn({
+        let node = node.clone();
+        async move {
+          while let Some(incoming) = node.endpoint.accept().await {
+            tokio::spawn(node.clone().accept(incoming));
+          }
+        }
+      });
+
+      let allowed = Peer::new(Id::from([1; Id::LEN]), (Ipv4Addr::LOCALHOST, 1).into());
+
+      node
+        .set_packages(
+          [(package.hash, package.clone())].into(),
+          BTreeSet::new(),
+          [(package.hash, Visibility::Allow([allowed.id].into()))].into(),
+        )
+        .await;
+
+      let client = Arc::new(
+        Node::new(
+          Ipv4Addr::LOCALHOST.into(),
+          None,
+          Limits::default(),
+          None,
+          BTreeMap::new(),
+          0,
+          None,
+        )
+        .await
+        .unwrap(),
+      );
+
+      let connection = client.connect(node.peer()).await.unwrap();
+
+      let (mut tx, mut rx) = connection.open_bi().await.unwrap();
+
+      client
+        .write(node.peer(), &mut tx, Message::Relayed(allowed))
+        .await
+        .unwrap();
+
+      client
+        .send(node.peer(), &mut tx, Message::Search)
+        .await
+        .unwrap();
+
+      assert_matches!(
+        client
+          .read::<response::Search>(node.peer(), &mut rx)
+          .await
+          .err(),
+        Some(Error::Read { .. }),
+      );
+
+      client.ping(node.peer()).await.unwrap();
+
+      assert_eq!(client.search(node.id).await.unwrap(), Some(Vec::new()));
+
+      server.abort();
+    });
+  }
+
+  #[test]
+  fn connections_beyond_limit_are_refused() {
+    Runtime::new().unwrap().block_on(async {
+      let node = Arc::new(
+        Node::new(
+          Ipv4Addr::LOCALHOST.into(),
+          None,
+          Limits {
+            max_connections: 1,
+            ..Default::default()
+          },
+          None,
+          BTreeMap::new(),
+          0,
+          None,
+        )
+        .await
+        .unwrap(),
+      );
+
+      let server = {
+        let node = node.clone();
+        tokio::spawn(async move {
+          while let Some(incoming) = node.endpoint.accept().await {
+            tokio::spawn(node.clone().accept(incoming));
+          }
+        })
+      };
+
+      let mut clients = Vec::new();
+
+      for _ in 0..2 {
+        clients.push(Arc::new(
+          Node::new(
+            Ipv4Addr::LOCALHOST.into(),
+            None,
+            Limits::default(),
+            None,
+            BTreeMap::new(),
+            0,
+            None,
+          )
+          .await
+          .unwrap(),
+        ));
+      }
+
+      clients[0].ping(node.peer()).await.unwrap();
+
+      assert_matches!(
+        clients[1].ping(node.peer()).await,
+        Err(Error::Connection { .. }),
+      );
+
+      assert_eq!(node.incoming(), 1);
+
+      server.abort();
+    });
+  }
+
+  #[test]
+  fn idle_connections_release_permits() {
+    Runtime::new().unwrap().block_on(async {
+      let node = Arc::new(
+        Node::new(
+          Ipv4Addr::LOCALHOST.into(),
+          None,
+          Limits {
+            max_connections: 1,
+            ..Default::default()
+          },
+          None,
+          BTreeMap::new(),
+          0,
+          None,
+        )
+        .await
+        .unwrap(),
+      );
+
+      let server = {
+        let node = node.clone();
+        tokio::spawn(async move {
+          while let Some(incoming) = node.endpoint.accept().await {
+            tokio::spawn(node.clone().accept(incoming));
+          }
+        })
+      };
+
+      let mut clients = Vec::new();
+
+      for _ in 0..2 {
+        clients.push(Arc::new(
+          Node::new(
+            Ipv4Addr::LOCALHOST.into(),
+            None,
+            Limits::default(),
+            None,
+            BTreeMap::new(),
+            0,
+            None,
+          )
+          .await
+          .unwrap(),
+        ));
+      }
+
+      // keep-alives hold the connection open, but do not count as activity
+      clients[0].rendezvous(node.peer()).await.unwrap();
+
+      assert_eq!(node.incoming(), 1);
+
+      tokio::time::sleep(Node::IDLE_TIMEOUT * 3).await;
+
+      assert_eq!(node.incoming(), 0);
+
+      assert_eq!(clients[0].pool_stats().connections, 0);
+
+      clients[1].ping(node.peer()).await.unwrap();
+
+      server.abort();
+    });
+  }
+
+  #[test]
+  fn uploads_are_throttled() {
+    Runtime::new().unwrap().block_on(async {
+      let tempdir = tempdir();
+
+      let output = tempdir.join("comic.package");
+
+      subcommand::package::Package {
+        compress: false,
+        optimize: Default::default(),
+        root: "tests/packages/comic".into(),
+        output: output.clone(),
+        thumbnails: false,
+      }
+      .run()
+      .unwrap();
+
+      let package = Package::load(&output).unwrap();
+
+      let (&hash, content) = package
+        .files
+        .iter()
+        .find(|(_hash, content)| content.data.len() > 3000)
+        .unwrap();
+
+      let owner = Arc::new(
+        Node::new(
+          Ipv4Addr::LOCALHOST.into(),
+          None,
+          Limits {
+            upload: Some(2000),
+            ..Default::default()
+          },
+          None,
+          [(package.hash, Arc::new(package.clone()))].into(),
+          0,
+          None,
+        )
+        .await
+        .unwrap(),
+      );
+
+      let server = {
+        let owner = owner.clone();
+        tokio::spawn(async move {
+          while let Some(incoming) = owner.endpoint.accept().await {
+            tokio::spawn(owner.clone().accept(incoming));
+          }
+        })
+      };
+
+      let node = Arc::new(
+        Node::new(
+          Ipv4Addr::LOCALHOST.into(),
+          None,
+          Limits::default(),
+          None,
+          BTreeMap::new(),
+          0,
+          None,
+        )
+        .await
+        .unwrap(),
+      );
+
+      node.ping(owner.peer()).await.unwrap();
+
+      let start = Instant::now();
+
+      assert_eq!(
+        node.file(owner.id, hash).await.unwrap().unwrap(),
+        content.decompress().as_ref(),
+      );
+
+      let expected = Duration::from_secs_f64((content.data.len() - 2000) as f64 / 2000.0);
+
+      assert!(start.elapsed() >= expected.mul_f64(0.9));
+
+      server.abort();
+    });
+  }
+
+  #[test]
+  fn unresponsive_peers_are_evicted() {
+    Runtime::new().unwrap().block_on(async {
+      let node = Arc::new(
+        Node::new(
+          Ipv4Addr::LOCALHOST.into(),
+          None,
+          Limits::default(),
+          None,
+          BTreeMap::new(),
+          0,
+          None,
+        )
+        .await
+        .unwrap(),
+      );
+
+      let live = Arc::new(
+        Node::new(
+          Ipv4Addr::LOCALHOST.into(),
+          None,
+          Limits::default(),
+          None,
+          BTreeMap::new(),
+          0,
+          None,
+        )
+        .await
+        .unwrap(),
+      );
+
+      let server = {
+        let live = live.clone();
+        tokio::spawn(async move {
+          while let Some(incoming) = live.endpoint.accept().await {
+            tokio::spawn(live.clone().accept(incoming));
+          }
+        })
+      };
+
+      node.ping(live.peer()).await.unwrap();
+
+      let socket = std::net::UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+
+      let dead = Peer::new(random_id(), socket.local_addr().unwrap());
+
+      node
+        .local
+        .write()
+        .await
+        .insert(dead.id, Contact::new(dead, Duration::ZERO));
+
+      for failures in 1..Node::MAX_FAILURES {
+        node.check_health().await;
+        assert_eq!(node.local.read().await[&dead.id].failures, failures);
+      }
+
+      node.check_health().await;
+
+      let local = node.local.read().await;
+
+      assert_eq!(local.keys().collect::<Vec<&Id>>(), [&live.id]);
+
+      assert_eq!(local[&live.id].failures, 0);
+
+      assert!(local[&live.id].rtt > Duration::ZERO);
+
+      server.abort();
+    });
+  }
+
+  #[test]
+  fn peer_table_is_restored_and_revalidated() {
+    Runtime::new().unwrap().block_on(async {
+      let dir = tempdir();
+
+      let node = Arc::new(
+        Node::new(
+          Ipv4Addr::LOCALHOST.into(),
+          Some(dir.path_utf8()),
+          Limits::default(),
+          None,
+          BTreeMap::new(),
+          0,
+          None,
+        )
+        .await
+        .unwrap(),
+      );
+
+      let live = Arc::new(
+        Node::new(
+          Ipv4Addr::LOCALHOST.into(),
+          None,
+          Limits::default(),
+          None,
+          BTreeMap::new(),
+          0,
+          None,
+        )
+        .await
+        .unwrap(),
+      );
+
+      let server = {
+        let live = live.clone();
+        tokio::spawn(async move {
+          while let Some(incoming) = live.endpoint.accept().await {
+            tokio::spawn(live.clone().accept(incoming));
+          }
+        })
+      };
+
+      let socket = std::net::UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+
+      let dead = Peer::new(random_id(), socket.local_addr().unwrap());
+
+      node.ping(live.peer()).await.unwrap();
+
+      node
+        .local
+        .write()
+        .await
+        .insert(dead.id, Contact::new(dead, Duration::ZERO));
+
+      node.save_peers().await.unwrap();
+
+      let restarted = Arc::new(
+        Node::new(
+          Ipv4Addr::LOCALHOST.into(),
+          Some(dir.path_utf8()),
+          Limits::default(),
+          None,
+          BTreeMap::new(),
+          0,
+          None,
+        )
+        .await
+        .unwrap(),
+      );
+
+      assert_eq!(restarted.load_peers().await.unwrap().len(), 2);
+
+      restarted.restore_peers().await.unwrap();
+
+      assert_eq!(
+        restarted.local.read().await.keys().collect::<Vec<&Id>>(),
+        [&live.id],
+      );
+
+      server.abort();
+    });
+  }
+
+  #[test]
+  fn missing_peer_table_is_empty() {
+    Runtime::new().unwrap().block_on(async {
+      let dir = tempdir();
+
+      let node = Node::new(
+        Ipv4Addr::LOCALHOST.into(),
+        Some(dir.path_utf8()),
+        Limits::default(),
+        None,
+        BTreeMap::new(),
+        0,
+        None,
+      )
+      .await
+      .unwrap();
+
+      assert!(node.load_peers().await.unwrap().is_empty());
+
+      dir.write("peers.json", "garbage");
+
+      assert_matches!(
+        node.load_peers().await,
+        Err(Error::PeerTableDeserialize { .. }),
+      );
+    });
+  }
+
+  #[test]
+  fn bootstrap_succeeds_if_any_peer_is_reachable() {
+    Runtime::new().unwrap().block_on(async {
+      let node = Arc::new(
+        Node::new(
+          Ipv4Addr::LOCALHOST.into(),
+          None,
+          Limits::default(),
+          None,
+          BTreeMap::new(),
+          0,
+          None,
+        )
+        .await
+        .unwrap(),
+      );
+
+      let live = Arc::new(
+        Node::new(
+          Ipv4Addr::LOCALHOST.into(),
+          None,
+          Limits::default(),
+          None,
+          BTreeMap::new(),
+          0,
+          None,
+        )
+        .await
+        .unwrap(),
+      );
+
+      let server = {
+        let live = live.clone();
+        tokio::spawn(async move {
+          while let Some(incoming) = live.endpoint.accept().await {
+            tokio::spawn(live.clone().accept(incoming));
+          }
+        })
+      };
+
+      let socket = std::net::UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+
+      let dead = Peer::new(random_id(), socket.local_addr().unwrap());
+
+      node.bootstrap(&[dead.into(), live.peer().into()]).await;
+
+      assert_eq!(
+        node.local.read().await.keys().collect::<Vec<&Id>>(),
+        [&live.id],
+      );
+
+      server.abort();
+    });
+  }
+
+  #[test]
+  fn network_key_restricts_membership() {
+    Runtime::new().unwrap().block_on(async {
+      async fn node(network_key: Option<&str>) -> Arc<Node> {
+        let node = Arc::new(
+          Node::new(
+            Ipv4Addr::LOCALHOST.into(),
+            None,
+            Limits::default(),
+            network_key.map(|key| key.parse().unwrap()),
+            BTreeMap::new(),
+            0,
+            None,
+          )
+          .await
+          .unwrap(),
+        );
+
+        tokio::spawn({
+          let node = node.clone();
+          async move {
+            while let Some(incoming) = node.endpoint.accept().await {
+              tokio::spawn(node.clone().accept(incoming));
+            }
+          }
+        });
+
+        node
+      }
+
+      let public = node(None).await;
+      let foo = node(Some("foo")).await;
+      let bar = node(Some("bar")).await;
+      let baz = node(Some("foo")).await;
+
+      foo.ping(baz.peer()).await.unwrap();
+      baz.ping(foo.peer()).await.unwrap();
+
+      assert_eq!(foo.search(baz.id).await.unwrap(), Some(Vec::new()));
+
+      assert_matches!(foo.ping(bar.peer()).await, Err(Error::Connection { .. }));
+      assert_matches!(foo.ping(public.peer()).await, Err(Error::Connection { .. }));
+      assert_matches!(public.ping(foo.peer()).await, Err(Error::Connection { .. }));
+
+      public.ping(node(None).await.peer()).await.unwrap();
+    });
+  }
+
+  #[test]
+  fn visibility_is_enforced() {
+    Runtime::new().unwrap().block_on(async {
+      let tempdir = tempdir();
+
+      let output = tempdir.join("comic.package");
+
+      subcommand::package::Package {
+        compress: false,
+        optimize: Default::default(),
+        root: "tests/packages/comic".into(),
+        output: output.clone(),
+        thumbnails: false,
+      }
+      .run()
+      .unwrap();
+
+      let package = Arc::new(Package::load(&output).unwrap());
+
+      // allow lists are only honored for peers authenticated by a network key
+      for network_key in [None, Some("foo")] {
+        visibility(&package, network_key.map(|key| key.parse().unwrap())).await;
+      }
+    });
+
+    async fn visibility(package: &Arc<Package>, network_key: Option<NetworkKey>) {
+      let build = || async {
+        Arc::new(
+          Node::new(
+            Ipv4Addr::LOCALHOST.into(),
+            None,
+            Limits::default(),
+            network_key,
+            BTreeMap::new(),
+            0,
+            None,
+          )
+          .await
+          .unwrap(),
+        )
+      };
+
+      let owner = build().await;
+
+      let server = {
+        let owner = owner.clone();
+        tokio::spawn(async move {
+          while let Some(incoming) = owner.endpoint.accept().await {
+            tokio::spawn(owner.clone().accept(incoming));
+          }
+        })
+      };
+
+      let friend = build().await;
+
+      let stranger = build().await;
+
+      friend.ping(owner.peer()).await.unwrap();
+      stranger.ping(owner.peer()).await.unwrap();
+
+      let file = *package.files.keys().next().unwrap();
+
+      for visibility in [
+        Visibility::Private,
+        Visibility::Allow([friend.id].into()),
+        Visibility::Public,
+      ] {
+        owner
+          .set_packages(
+            [(package.hash, package.clone())].into(),
+            BTreeSet::new(),
+            [(package.hash, visibility.clone())].into(),
+          )
+          .await;
+
+        for node in [&friend, &stranger] {
+          let allowed = visibility.allows(network_key.is_some().then_some(node.id));
+
+          assert_eq!(
+            node.search(owner.id).await.unwrap().unwrap().is_empty(),
+            !allowed,
+          );
+
+          assert_eq!(
+            node
+              .query(owner.id, "comic")
+              .await
+              .unwrap()
+              .unwrap()
+              .is_empty(),
+            !allowed,
+          );
+
+          assert_eq!(
+            node.get(owner.id, package.hash).await.unwrap().is_some(),
+            allowed,
+          );
+
+          assert_eq!(node.file(owner.id, file).await.unwrap().is_some(), allowed,);
+        }
+      }
+
+      server.abort();
+    }
+  }
+}
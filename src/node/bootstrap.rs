@@ -0,0 +1,133 @@
+use super::*;
+
+#[derive(Debug, Snafu)]
+#[snafu(context(suffix(Error)))]
+pub(crate) enum Error {
+  #[snafu(display("invalid bootstrap peer ID `{input}`"))]
+  Id {
+    input: String,
+    source: blake3::HexError,
+  },
+  #[snafu(display("invalid bootstrap peer `{input}`"))]
+  Invalid { input: String },
+  #[snafu(display("failed to resolve bootstrap peer address `{address}`"))]
+  Resolve { address: String, source: io::Error },
+  #[snafu(display("bootstrap peer address `{address}` did not resolve to any IP addresses"))]
+  Unresolved { address: String },
+}
+
+// host names are kept unresolved, so that they are looked up again on each
+// bootstrap attempt instead of once, blocking, at startup
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Bootstrap {
+  pub(crate) address: String,
+  pub(crate) id: Id,
+}
+
+impl Bootstrap {
+  pub(crate) async fn resolve(&self) -> Result<Peer, Error> {
+    let address = &self.address;
+
+    let socket_addr = tokio::net::lookup_host(address)
+      .await
+      .context(ResolveError { address })?
+      .next()
+      .context(UnresolvedError { address })?;
+
+    Ok(Peer::new(self.id, socket_addr))
+  }
+}
+
+impl Display for Bootstrap {
+  fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+    write!(f, "{}@{}", self.id, self.address)
+  }
+}
+
+impl From<Peer> for Bootstrap {
+  fn from(peer: Peer) -> Self {
+    Self {
+      address: peer.socket_addr().to_string(),
+      id: peer.id,
+    }
+  }
+}
+
+impl FromStr for Bootstrap {
+  type Err = Error;
+
+  fn from_str(input: &str) -> Result<Self, Self::Err> {
+    static RE: Lazy<Regex> = lazy_regex!("^(.*)@(.*)$");
+
+    let captures = RE.captures(input).context(InvalidError { input })?;
+
+    let address = &captures[2];
+
+    ensure!(
+      address.parse::<SocketAddr>().is_ok()
+        || address
+          .rsplit_once(':')
+          .is_some_and(|(host, port)| !host.is_empty() && port.parse::<u16>().is_ok()),
+      InvalidError { input },
+    );
+
+    Ok(Self {
+      address: address.into(),
+      id: captures[1].parse().context(IdError { input })?,
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use {super::*, std::net::Ipv4Addr, tokio::runtime::Runtime};
+
+  #[test]
+  fn from_str() {
+    let id = "0f89ce0b671f7277b105035ea88341a81c5fceaed092eab29721fe6f86807133";
+
+    #[track_caller]
+    fn case(s: &str, address: &str) {
+      let bootstrap = s.parse::<Bootstrap>().unwrap();
+      assert_eq!(bootstrap.address, address);
+      assert_eq!(bootstrap.to_string(), s);
+    }
+
+    case(&format!("{id}@1.2.3.4:5"), "1.2.3.4:5");
+    case(&format!("{id}@[fe80::1%3]:5"), "[fe80::1%3]:5");
+    case(&format!("{id}@example.com:5"), "example.com:5");
+
+    assert_matches!(
+      format!("{id}@example.com").parse::<Bootstrap>(),
+      Err(Error::Invalid { .. }),
+    );
+
+    assert_matches!(
+      format!("{id}@:5").parse::<Bootstrap>(),
+      Err(Error::Invalid { .. }),
+    );
+
+    assert_matches!("foo@1.2.3.4:5".parse::<Bootstrap>(), Err(Error::Id { .. }));
+  }
+
+  #[test]
+  fn resolve() {
+    Runtime::new().unwrap().block_on(async {
+      let id = "0f89ce0b671f7277b105035ea88341a81c5fceaed092eab29721fe6f86807133";
+
+      let peer = format!("{id}@localhost:5")
+        .parse::<Bootstrap>()
+        .unwrap()
+        .resolve()
+        .await
+        .unwrap();
+
+      assert!(peer.ip.is_loopback());
+      assert_eq!(peer.port, 5);
+
+      let peer = Peer::new(id.parse().unwrap(), (Ipv4Addr::LOCALHOST, 5).into());
+
+      assert_eq!(Bootstrap::from(peer).resolve().await.unwrap(), peer);
+    });
+  }
+}
@@ -0,0 +1,20 @@
+use super::*;
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Contact {
+  pub(crate) failures: u32,
+  pub(crate) last_seen: Instant,
+  pub(crate) peer: Peer,
+  pub(crate) rtt: Duration,
+}
+
+impl Contact {
+  pub(crate) fn new(peer: Peer, rtt: Duration) -> Self {
+    Self {
+      failures: 0,
+      last_seen: Instant::now(),
+      peer,
+      rtt,
+    }
+  }
+}
@@ -0,0 +1,383 @@
+use {super::*, tokio::net::UdpSocket};
+
+pub(crate) const DEFAULT_GROUPS: [IpAddr; 2] = [
+  IpAddr::V4(Ipv4Addr::new(239, 4, 9, 151)),
+  // link-local scope
+  IpAddr::V6(Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0x4, 0x9, 0x151)),
+];
+// generic multicast application port:
+// https://datatracker.ietf.org/doc/draft-karstens-pim-multicast-application-ports/
+pub(crate) const DEFAULT_PORT: u16 = 49151;
+
+const ADVERTISEMENT_INTERVAL: Duration = Duration::from_secs(5 * 60);
+const MAX_ADVERTISEMENT_LEN: usize = 512;
+const MAX_PENDING_PINGS: usize = 16;
+const MAX_TRACKED_SOURCES: usize = 1024;
+const RATE_LIMIT_INTERVAL: Duration = Duration::from_secs(10);
+const RECEIVE_ERROR_DELAY: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+pub(crate) struct Advertisement {
+  pub(crate) id: Id,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub(crate) mac: Option<Hash>,
+  pub(crate) port: u16,
+}
+
+impl Advertisement {
+  pub(crate) fn new(id: Id, port: u16, network_key: Option<NetworkKey>) -> Self {
+    Self {
+      id,
+      mac: network_key.map(|key| Self::mac(key, id, port)),
+      port,
+    }
+  }
+
+  fn mac(key: NetworkKey, id: Id, port: u16) -> Hash {
+    key.mac("advertisement", &[id], &port.to_le_bytes())
+  }
+
+  pub(crate) fn is_authentic(&self, network_key: Option<NetworkKey>) -> bool {
+    match (network_key, self.mac) {
+      (Some(key), Some(mac)) => Self::mac(key, self.id, self.port) == mac,
+      (None, None) => true,
+      _ => false,
+    }
+  }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Config {
+  pub(crate) groups: Vec<IpAddr>,
+  pub(crate) interface: Option<u32>,
+  pub(crate) port: u16,
+}
+
+impl Default for Config {
+  fn default() -> Self {
+    Self {
+      groups: DEFAULT_GROUPS.into(),
+      interface: None,
+      port: DEFAULT_PORT,
+    }
+  }
+}
+
+impl Config {
+  pub(crate) fn group_addr(&self, group: IpAddr) -> SocketAddr {
+    match group {
+      IpAddr::V4(group) => (group, self.port).into(),
+      IpAddr::V6(group) => {
+        SocketAddrV6::new(group, self.port, 0, self.interface.unwrap_or_default()).into()
+      }
+    }
+  }
+}
+
+#[derive(Default)]
+pub(crate) struct RateLimiter {
+  seen: HashMap<IpAddr, Instant>,
+}
+
+impl RateLimiter {
+  pub(crate) fn allow(&mut self, ip: IpAddr, now: Instant) -> bool {
+    if self.seen.len() >= MAX_TRACKED_SOURCES {
+      self
+        .seen
+        .retain(|_ip, last| now.duration_since(*last) < RATE_LIMIT_INTERVAL);
+    }
+
+    match self.seen.get(&ip) {
+      Some(last) if now.duration_since(*last) < RATE_LIMIT_INTERVAL => false,
+      _ if self.seen.len() >= MAX_TRACKED_SOURCES => false,
+      _ => {
+        self.seen.insert(ip, now);
+        true
+      }
+    }
+  }
+}
+
+pub(crate) fn interface_index(interface: &str) -> io::Result<u32> {
+  if let Ok(index) = interface.parse() {
+    return Ok(index);
+  }
+
+  #[cfg(unix)]
+  {
+    let name = std::ffi::CString::new(interface)
+      .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+
+    match unsafe { libc::if_nametoindex(name.as_ptr()) } {
+      0 => Err(io::Error::last_os_error()),
+      index => Ok(index),
+    }
+  }
+
+  #[cfg(not(unix))]
+  Err(io::Error::new(
+    io::ErrorKind::InvalidInput,
+    "interface must be a numeric index",
+  ))
+}
+
+pub(crate) fn socket(group: IpAddr, config: &Config) -> io::Result<UdpSocket> {
+  use socket2::{Domain, InterfaceIndexOrAddress, Protocol, Socket, Type};
+
+  let socket = Socket::new(
+    Domain::for_address(config.group_addr(group)),
+    Type::DGRAM,
+    Some(Protocol::UDP),
+  )?;
+
+  #[cfg(unix)]
+  socket.set_reuse_port(true)?;
+
+  match group {
+    IpAddr::V4(group) => {
+      socket.bind(&SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), config.port).into())?;
+
+      match config.interface {
+        Some(index) => {
+          socket.join_multicast_v4_n(&group, &InterfaceIndexOrAddress::Index(index))?
+        }
+        None => socket.join_multicast_v4(&group, &Ipv4Addr::UNSPECIFIED)?,
+      }
+    }
+    IpAddr::V6(group) => {
+      socket.set_only_v6(true)?;
+
+      socket.bind(&SocketAddr::new(Ipv6Addr::UNSPECIFIED.into(), config.port).into())?;
+
+      let index = config.interface.unwrap_or_default();
+
+      socket.join_multicast_v6(&group, index)?;
+
+      if index != 0 {
+        socket.set_multicast_if_v6(index)?;
+      }
+    }
+  }
+
+  socket.set_nonblocking(true)?;
+
+  UdpSocket::from_std(socket.into())
+}
+
+pub(crate) async fn listen(node: Arc<Node>, socket: Arc<UdpSocket>) {
+  let mut limiter = RateLimiter::default();
+  let pending = Arc::new(tokio::sync::Semaphore::new(MAX_PENDING_PINGS));
+  let mut buffer = [0; MAX_ADVERTISEMENT_LEN];
+
+  loop {
+    let (len, source) = match socket.recv_from(&mut buffer).await {
+      Ok(received) => received,
+      Err(err) => {
+        log::warn!("failed to receive advertisement: {err}");
+        tokio::time::sleep(RECEIVE_ERROR_DELAY).await;
+        continue;
+      }
+    };
+
+    let advertisement = match Advertisement::from_cbor(&buffer[..len]) {
+      Ok(advertisement) => advertisement,
+      Err(err) => {
+        log::debug!("ignoring malformed advertisement from {source}: {err}");
+        continue;
+      }
+    };
+
+    if advertisement.id == node.id {
+      continue;
+    }
+
+    if advertisement.port == 0 {
+      log::debug!("ignoring advertisement with invalid port from {source}");
+      continue;
+    }
+
+    if !advertisement.is_authentic(node.network_key) {
+      log::debug!("ignoring advertisement from {source} from another network");
+      continue;
+    }
+
+    if !limiter.allow(source.ip(), Instant::now()) {
+      log::debug!("ignoring rate-limited advertisement from {source}");
+      continue;
+    }
+
+    let Ok(permit) = pending.clone().try_acquire_owned() else {
+      log::debug!("ignoring advertisement from {source}: too many pending pings");
+      continue;
+    };
+
+    let mut address = source;
+    address.set_port(advertisement.port);
+
+    let peer = Peer::new(advertisement.id, address);
+
+    log::info!("advertisement from peer: {peer}");
+
+    let node = node.clone();
+    tokio::spawn(async move {
+      if let Err(err) = node.ping(peer).await {
+        log::warn!("failed to ping advertised peer {peer}: {err}");
+      }
+      drop(permit);
+    });
+  }
+}
+
+pub(crate) async fn advertise(node: Arc<Node>, socket: Arc<UdpSocket>, group: SocketAddr) {
+  let advertisement = Advertisement::new(node.id, node.port, node.network_key).to_cbor();
+
+  // make a local connection to ensure we are accepting incoming
+  // connections before sending our first advertisement
+  let local = Peer {
+    ip: match node.ip {
+      IpAddr::V4(_) => Ipv4Addr::LOCALHOST.into(),
+      IpAddr::V6(_) => Ipv6Addr::LOCALHOST.into(),
+    },
+    ..node.peer()
+  };
+
+  match node.connect(local).await {
+    Ok(connection) => connection.close(0u32.into(), b""),
+    Err(err) => log::warn!("failed to connect to self before advertising: {err}"),
+  }
+
+  loop {
+    if let Err(err) = socket.send_to(&advertisement, group).await {
+      log::warn!("failed to send advertisement to {group}: {err}");
+    }
+
+    tokio::time::sleep(ADVERTISEMENT_INTERVAL).await;
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use {super::*, tokio::runtime::Runtime};
+
+  #[test]
+  fn group_addr() {
+    let config = Config {
+      interface: Some(2),
+      ..Default::default()
+    };
+
+    assert_eq!(
+      config.group_addr(DEFAULT_GROUPS[0]),
+      "239.4.9.151:49151".parse::<SocketAddr>().unwrap(),
+    );
+
+    assert_eq!(
+      config.group_addr(DEFAULT_GROUPS[1]),
+      "[ff02::4:9:151%2]:49151".parse::<SocketAddr>().unwrap(),
+    );
+  }
+
+  #[test]
+  fn interface_index() {
+    assert_eq!(super::interface_index("7").unwrap(), 7);
+
+    #[cfg(target_os = "linux")]
+    assert_eq!(super::interface_index("lo").unwrap(), 1);
+
+    assert!(super::interface_index("this-interface-does-not-exist").is_err());
+  }
+
+  #[test]
+  fn rate_limiter() {
+    let mut limiter = RateLimiter::default();
+
+    let now = Instant::now();
+
+    let a = IpAddr::from(Ipv4Addr::new(10, 0, 0, 1));
+    let b = IpAddr::from(Ipv4Addr::new(10, 0, 0, 2));
+
+    assert!(limiter.allow(a, now));
+    assert!(!limiter.allow(a, now + RATE_LIMIT_INTERVAL / 2));
+    assert!(limiter.allow(b, now + RATE_LIMIT_INTERVAL / 2));
+    assert!(limiter.allow(a, now + RATE_LIMIT_INTERVAL));
+  }
+
+  #[test]
+  fn garbage_datagrams_are_ignored() {
+    Runtime::new().unwrap().block_on(async {
+      let node = Arc::new(
+        Node::new(
+          Ipv4Addr::LOCALHOST.into(),
+          None,
+          Limits::default(),
+          None,
+          BTreeMap::new(),
+          0,
+          None,
+        )
+        .await
+        .unwrap(),
+      );
+
+      let live = Arc::new(
+        Node::new(
+          Ipv4Addr::LOCALHOST.into(),
+          None,
+          Limits::default(),
+          None,
+          BTreeMap::new(),
+          0,
+          None,
+        )
+        .await
+        .unwrap(),
+      );
+
+      let server = {
+        let live = live.clone();
+        tokio::spawn(async move {
+          while let Some(incoming) = live.endpoint.accept().await {
+            tokio::spawn(live.clone().accept(incoming));
+          }
+        })
+      };
+
+      let socket = Arc::new(UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap());
+
+      let address = socket.local_addr().unwrap();
+
+      let listener = tokio::spawn(listen(node.clone(), socket));
+
+      let sender = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+
+      for datagram in [
+        &[][..],
+        &[0xff; 64],
+        &[0; MAX_ADVERTISEMENT_LEN * 2],
+        &Advertisement::new(live.id, 0, None).to_cbor(),
+        &Advertisement::new(live.id, live.port, Some("foo".parse().unwrap())).to_cbor(),
+        &Advertisement::new(node.id, node.port, None).to_cbor(),
+        &Advertisement::new(live.id, live.port, None).to_cbor(),
+      ] {
+        sender.send_to(datagram, address).await.unwrap();
+      }
+
+      for _ in 0..100 {
+        if !node.local.read().await.is_empty() {
+          break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+      }
+
+      assert_eq!(
+        node.local.read().await.keys().collect::<Vec<&Id>>(),
+        [&live.id],
+      );
+
+      assert!(!listener.is_finished());
+
+      listener.abort();
+      server.abort();
+    });
+  }
+}
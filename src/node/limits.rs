@@ -0,0 +1,23 @@
+use super::*;
+
+pub(crate) const DEFAULT_MAX_CONNECTIONS: usize = 256;
+pub(crate) const DEFAULT_MAX_STREAMS: u32 = 32;
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub(crate) struct Limits {
+  pub(crate) download: Option<u64>,
+  pub(crate) max_connections: usize,
+  pub(crate) max_streams: u32,
+  pub(crate) upload: Option<u64>,
+}
+
+impl Default for Limits {
+  fn default() -> Self {
+    Self {
+      download: None,
+      max_connections: DEFAULT_MAX_CONNECTIONS,
+      max_streams: DEFAULT_MAX_STREAMS,
+      upload: None,
+    }
+  }
+}
@@ -0,0 +1,159 @@
+use {super::*, std::sync::Mutex};
+
+#[derive(Debug, Default, Deserialize, PartialEq, Serialize)]
+pub(crate) struct Stats {
+  pub(crate) connections: u64,
+  pub(crate) opened: u64,
+  pub(crate) reused: u64,
+}
+
+#[derive(Default)]
+pub(crate) struct Pool {
+  inbound: Mutex<HashMap<Id, Connection>>,
+  opened: AtomicU64,
+  reused: AtomicU64,
+  slots: Mutex<HashMap<Id, Arc<tokio::sync::Mutex<Option<Connection>>>>>,
+}
+
+impl Pool {
+  pub(crate) async fn get<F: Future<Output = Result<Connection>>>(
+    &self,
+    id: Id,
+    connect: impl FnOnce() -> F,
+  ) -> Result<Connection> {
+    let slot = self.slots.lock().unwrap().entry(id).or_default().clone();
+
+    let mut slot = slot.lock().await;
+
+    if let Some(connection) = slot.as_ref() {
+      if connection.close_reason().is_none() {
+        self.reused.fetch_add(1, atomic::Ordering::Relaxed);
+        return Ok(connection.clone());
+      }
+    }
+
+    *slot = None;
+
+    let connection = connect().await?;
+
+    self.opened.fetch_add(1, atomic::Ordering::Relaxed);
+
+    *slot = Some(connection.clone());
+
+    Ok(connection)
+  }
+
+  pub(crate) fn insert(&self, id: Id, connection: Connection) {
+    let slot = self.slots.lock().unwrap().entry(id).or_default().clone();
+
+    let Ok(mut guard) = slot.try_lock() else {
+      return;
+    };
+
+    if guard
+      .as_ref()
+      .is_none_or(|pooled| pooled.close_reason().is_some())
+    {
+      *guard = Some(connection);
+    }
+  }
+
+  // inbound connections under ids that are only claimed are kept apart, so
+  // they can be used to reach the peer that opened them, but never replace
+  // or satisfy a dial
+  pub(crate) fn accept(&self, id: Id, connection: Connection) {
+    self.inbound.lock().unwrap().insert(id, connection);
+  }
+
+  pub(crate) fn reachable(&self, id: Id) -> Option<Connection> {
+    self.connection(id).or_else(|| {
+      self
+        .inbound
+        .lock()
+        .unwrap()
+        .get(&id)
+        .filter(|connection| connection.close_reason().is_none())
+        .cloned()
+    })
+  }
+
+  pub(crate) fn connection(&self, id: Id) -> Option<Connection> {
+    let slot = self.slots.lock().unwrap().get(&id).cloned()?;
+
+    let guard = slot.try_lock().ok()?;
+
+    guard
+      .as_ref()
+      .filter(|connection| connection.close_reason().is_none())
+      .cloned()
+  }
+
+  pub(crate) fn connections(&self) -> Vec<(Id, Connection)> {
+    let ids = self
+      .slots
+      .lock()
+      .unwrap()
+      .keys()
+      .copied()
+      .collect::<Vec<Id>>();
+
+    ids
+      .into_iter()
+      .filter_map(|id| Some((id, self.connection(id)?)))
+      .collect()
+  }
+
+  pub(crate) fn remove(&self, id: Id, connection: &Connection) {
+    {
+      let mut inbound = self.inbound.lock().unwrap();
+
+      if inbound
+        .get(&id)
+        .is_some_and(|accepted| accepted.stable_id() == connection.stable_id())
+      {
+        inbound.remove(&id);
+      }
+    }
+
+    let Some(slot) = self.slots.lock().unwrap().get(&id).cloned() else {
+      return;
+    };
+
+    let Ok(mut guard) = slot.try_lock() else {
+      return;
+    };
+
+    if guard
+      .as_ref()
+      .is_some_and(|pooled| pooled.stable_id() == connection.stable_id())
+    {
+      *guard = None;
+    }
+  }
+
+  pub(crate) fn stats(&self) -> Stats {
+    self
+      .inbound
+      .lock()
+      .unwrap()
+      .retain(|_id, connection| connection.close_reason().is_none());
+
+    let mut slots = self.slots.lock().unwrap();
+
+    slots.retain(|_id, slot| {
+      let Ok(slot) = slot.try_lock() else {
+        return true;
+      };
+
+      slot
+        .as_ref()
+        .is_some_and(|connection| connection.close_reason().is_none())
+    });
+
+    Stats {
+      connections: slots.len().into_u64(),
+      opened: self.opened.load(atomic::Ordering::Relaxed),
+      reused: self.reused.load(atomic::Ordering::Relaxed),
+    }
+  }
+}
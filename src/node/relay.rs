@@ -0,0 +1,84 @@
+use super::*;
+
+pub(crate) const DEFAULT_MAX_STREAMS: usize = 64;
+
+const IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct Config {
+  pub(crate) bandwidth: Option<u64>,
+  pub(crate) max_streams: usize,
+}
+
+#[derive(Debug, Default, Deserialize, PartialEq, Serialize)]
+pub(crate) struct Stats {
+  pub(crate) bandwidth: Option<u64>,
+  pub(crate) bytes: u64,
+  pub(crate) streams: u64,
+}
+
+pub(crate) struct Relay {
+  bandwidth: Throttle,
+  bytes: AtomicU64,
+  config: Config,
+  streams: Arc<Semaphore>,
+}
+
+impl Relay {
+  pub(crate) fn new(config: Config) -> Self {
+    Self {
+      bandwidth: Throttle::new(config.bandwidth),
+      bytes: AtomicU64::default(),
+      config,
+      streams: Arc::new(Semaphore::new(config.max_streams)),
+    }
+  }
+
+  pub(crate) fn permit(&self) -> Option<OwnedSemaphorePermit> {
+    self.streams.clone().try_acquire_owned().ok()
+  }
+
+  pub(crate) fn stats(&self) -> Stats {
+    Stats {
+      bandwidth: self.bandwidth.rate(),
+      bytes: self.bytes.load(atomic::Ordering::Relaxed),
+      streams: (self.config.max_streams - self.streams.available_permits()).into_u64(),
+    }
+  }
+
+  pub(crate) async fn pipe(&self, source: IpAddr, mut rx: RecvStream, mut tx: SendStream) {
+    let mut buffer = vec![0; throttle::CHUNK_LEN];
+
+    loop {
+      let len = match tokio::time::timeout(IDLE_TIMEOUT, rx.read(&mut buffer)).await {
+        Ok(Ok(Some(len))) => len,
+        Ok(Ok(None)) => break,
+        Ok(Err(err)) => {
+          log::debug!("failed to read relayed stream: {err}");
+          tx.reset(0u32.into()).ok();
+          return;
+        }
+        Err(_) => {
+          log::debug!("relayed stream timed out");
+          tx.reset(0u32.into()).ok();
+          rx.stop(0u32.into()).ok();
+          return;
+        }
+      };
+
+      self.bandwidth.acquire(source, len).await;
+
+      if let Err(err) = tx.write_all(&buffer[..len]).await {
+        log::debug!("failed to write relayed stream: {err}");
+        rx.stop(0u32.into()).ok();
+        return;
+      }
+
+      self
+        .bytes
+        .fetch_add(len.into_u64(), atomic::Ordering::Relaxed);
+    }
+
+    tx.finish().ok();
+  }
+}
@@ -0,0 +1,144 @@
+use {super::*, std::sync::Mutex};
+
+pub(crate) const CHUNK_LEN: usize = 16 * 1024;
+
+const MAX_IDLE_LANES: usize = 1024;
+
+pub(crate) struct Bucket {
+  available: f64,
+  last: Instant,
+  rate: f64,
+}
+
+impl Bucket {
+  pub(crate) fn new(rate: u64, now: Instant) -> Self {
+    Self {
+      available: rate as f64,
+      last: now,
+      rate: rate as f64,
+    }
+  }
+
+  pub(crate) fn reserve(&mut self, len: u64, now: Instant) -> Duration {
+    self.available = (self.available
+      + now.saturating_duration_since(self.last).as_secs_f64() * self.rate)
+      .min(self.rate);
+
+    self.last = now.max(self.last);
+
+    self.available -= len as f64;
+
+    if self.available < 0.0 {
+      Duration::from_secs_f64(-self.available / self.rate)
+    } else {
+      Duration::ZERO
+    }
+  }
+}
+
+// Each source waits for its previous reservation before making the next, so
+// sources with many concurrent transfers get no more than their share.
+pub(crate) struct Throttle {
+  bucket: Option<Mutex<Bucket>>,
+  lanes: Mutex<HashMap<IpAddr, Arc<tokio::sync::Mutex<()>>>>,
+  rate: Option<u64>,
+}
+
+impl Throttle {
+  pub(crate) fn new(rate: Option<u64>) -> Self {
+    Self {
+      bucket: rate.map(|rate| Mutex::new(Bucket::new(rate, Instant::now()))),
+      lanes: Mutex::default(),
+      rate,
+    }
+  }
+
+  pub(crate) fn rate(&self) -> Option<u64> {
+    self.rate
+  }
+
+  pub(crate) async fn acquire(&self, source: IpAddr, len: usize) {
+    let Some(bucket) = &self.bucket else {
+      return;
+    };
+
+    let lane = {
+      let mut lanes = self.lanes.lock().unwrap();
+
+      if lanes.len() >= MAX_IDLE_LANES {
+        lanes.retain(|_source, lane| Arc::strong_count(lane) > 1);
+      }
+
+      lanes.entry(source).or_default().clone()
+    };
+
+    let _guard = lane.lock().await;
+
+    let delay = bucket
+      .lock()
+      .unwrap()
+      .reserve(len.into_u64(), Instant::now());
+
+    tokio::time::sleep(delay).await;
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use {super::*, tokio::runtime::Runtime};
+
+  #[test]
+  fn bucket() {
+    let now = Instant::now();
+
+    let mut bucket = Bucket::new(1000, now);
+
+    assert_eq!(bucket.reserve(1000, now), Duration::ZERO);
+    assert_eq!(bucket.reserve(500, now), Duration::from_millis(500));
+    assert_eq!(
+      bucket.reserve(500, now + Duration::from_millis(500)),
+      Duration::from_millis(500),
+    );
+    assert_eq!(
+      bucket.reserve(0, now + Duration::from_secs(10)),
+      Duration::ZERO
+    );
+    assert_eq!(
+      bucket.reserve(1000, now + Duration::from_secs(10)),
+      Duration::ZERO
+    );
+  }
+
+  #[test]
+  fn greedy_sources_do_not_starve_others() {
+    Runtime::new().unwrap().block_on(async {
+      let throttle = Arc::new(Throttle::new(Some(100_000)));
+
+      let greedy = IpAddr::from(Ipv4Addr::new(10, 0, 0, 1));
+      let polite = IpAddr::from(Ipv4Addr::new(10, 0, 0, 2));
+
+      throttle.acquire(greedy, 100_000).await;
+
+      let mut tasks = Vec::new();
+
+      for _ in 0..8 {
+        let throttle = throttle.clone();
+        tasks.push(tokio::spawn(async move {
+          throttle.acquire(greedy, 10_000).await;
+        }));
+      }
+
+      tokio::time::sleep(Duration::from_millis(10)).await;
+
+      let start = Instant::now();
+
+      throttle.acquire(polite, 10_000).await;
+
+      assert!(start.elapsed() < Duration::from_millis(250));
+
+      for task in tasks {
+        task.await.unwrap();
+      }
+    });
+  }
+}
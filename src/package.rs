@@ -1,8 +1,18 @@
+pub(crate) use writer::Writer;
+
 use super::*;
 
+mod writer;
+
 #[derive(Debug, Snafu)]
 #[snafu(context(suffix(false)), visibility(pub))]
 pub(crate) enum Error {
+  #[snafu(display("failed to decompress package file `{hash}`"))]
+  Decompress {
+    backtrace: Option<Backtrace>,
+    hash: Hash,
+    source: io::Error,
+  },
   #[snafu(display("failed to deserialize manifest"))]
   DeserializeManifest {
     backtrace: Option<Backtrace>,
@@ -30,23 +40,11 @@ pub(crate) enum Error {
     len: u64,
     source: TryFromIntError,
   },
-  #[snafu(display("I/O error reading file `{path}`"))]
-  FileIo {
-    backtrace: Option<Backtrace>,
-    path: Utf8PathBuf,
-    source: io::Error,
-  },
   #[snafu(transparent)]
   Io {
     backtrace: Option<Backtrace>,
     source: io::Error,
   },
-  #[snafu(display("I/O error copying from `{path}`"))]
-  IoCopy {
-    backtrace: Option<Backtrace>,
-    path: Utf8PathBuf,
-    source: io::Error,
-  },
   #[snafu(display(
     "unexpected package magic bytes {} (\"{}\")",
     hex::encode(bytes),
@@ -77,6 +75,16 @@ pub(crate) enum Error {
     missing: u64,
     backtrace: Option<Backtrace>,
   },
+  #[snafu(display("I/O error reading input"))]
+  ReadInput {
+    backtrace: Option<Backtrace>,
+    source: io::Error,
+  },
+  #[snafu(display("I/O error writing to spool"))]
+  SpoolIo {
+    backtrace: Option<Backtrace>,
+    source: io::Error,
+  },
   #[snafu(display("package has trailing {trailing} bytes"))]
   TrailingBytes {
     backtrace: Option<Backtrace>,
@@ -84,14 +92,58 @@ pub(crate) enum Error {
   },
 }
 
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Content {
+  pub(crate) compressed: bool,
+  pub(crate) data: Vec<u8>,
+}
+
+impl Content {
+  pub(crate) fn decompress(&self) -> Cow<'_, [u8]> {
+    if self.compressed {
+      Cow::Owned(zstd::decode_all(self.data.as_slice()).unwrap())
+    } else {
+      Cow::Borrowed(&self.data)
+    }
+  }
+
+  fn head(&self, n: u64) -> Vec<u8> {
+    let mut head = Vec::new();
+
+    if self.compressed {
+      zstd::Decoder::new(self.data.as_slice())
+        .unwrap()
+        .take(n)
+        .read_to_end(&mut head)
+        .unwrap();
+    } else {
+      head.extend(self.data.iter().take(n.try_into().unwrap_or(usize::MAX)));
+    }
+
+    head
+  }
+}
+
+impl From<Vec<u8>> for Content {
+  fn from(data: Vec<u8>) -> Self {
+    Self {
+      compressed: false,
+      data,
+    }
+  }
+}
+
 #[derive(Clone, Debug, PartialEq)]
 pub(crate) struct Package {
-  pub(crate) files: HashMap<Hash, Vec<u8>>,
+  pub(crate) files: HashMap<Hash, Content>,
   pub(crate) hash: Hash,
   pub(crate) manifest: Manifest,
 }
 
 impl Package {
+  const COMPRESSED: u64 = 1 << 63;
+  const COMPRESSION_LEVEL: i32 = zstd::DEFAULT_COMPRESSION_LEVEL;
+  const CONTENT_TYPE_HEAD: u64 = 64;
   pub(crate) const MAGIC_BYTES: &'static str = "MEDIA📦\r\n\x1a\n\0";
 
   pub(crate) fn load(path: &Utf8Path) -> Result<Self, Error> {
@@ -99,8 +151,14 @@ impl Package {
 
     let len = file.metadata()?.len();
 
-    let mut package = BufReader::new(file);
+    Self::read(BufReader::new(file), len)
+  }
+
+  pub(crate) fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+    Self::read(Cursor::new(bytes), bytes.len().into_u64())
+  }
 
+  fn read(mut package: impl Read + Seek, len: u64) -> Result<Self, Error> {
     let mut bytes = [0; Self::MAGIC_BYTES.len()];
 
     let mut read = 0;
@@ -127,12 +185,15 @@ impl Package {
 
     let hash_count = package.read_u64()?;
 
-    let mut hashes = Vec::<(Hash, u64)>::new();
+    let mut hashes = Vec::<(Hash, u64, bool)>::new();
 
     for i in 0..hash_count {
       let hash = package.read_hash()?;
       let len = package.read_u64()?;
 
+      let compressed = len & Self::COMPRESSED != 0;
+      let len = len & !Self::COMPRESSED;
+
       usize::try_from(len).context(FileLengthRange { len })?;
 
       if let Some(last) = i.checked_sub(1) {
@@ -145,7 +206,7 @@ impl Package {
         );
       }
 
-      hashes.push((hash, len));
+      hashes.push((hash, len, compressed));
     }
 
     let hash = hashes
@@ -153,18 +214,22 @@ impl Package {
       .context(ManifestIndexOutOfBounds { index })?
       .0;
 
-    let mut files = HashMap::<Hash, Vec<u8>>::new();
+    let mut files = HashMap::<Hash, Content>::new();
 
-    for (expected, len) in hashes {
-      let mut buffer = vec![0; len as usize];
+    for (expected, len, compressed) in hashes {
+      let mut data = vec![0; len as usize];
 
-      package.read_exact(&mut buffer)?;
+      package.read_exact(&mut data)?;
 
-      let actual = Hash::bytes(&buffer);
+      let actual = if compressed {
+        Hash::reader(zstd::Decoder::new(data.as_slice())?).context(Decompress { hash: expected })?
+      } else {
+        Hash::bytes(&data)
+      };
 
       ensure!(actual == expected, FileHashInvalid { expected, actual });
 
-      files.insert(expected, buffer);
+      files.insert(expected, Content { compressed, data });
     }
 
     let position = package.stream_position()?;
@@ -177,7 +242,8 @@ impl Package {
     );
 
     let manifest: Manifest =
-      ciborium::from_reader(Cursor::new(files.get(&hash).unwrap())).context(DeserializeManifest)?;
+      ciborium::from_reader(Cursor::new(files.get(&hash).unwrap().decompress()))
+        .context(DeserializeManifest)?;
 
     Self::verify(&files, &manifest, hash)?;
 
@@ -188,97 +254,81 @@ impl Package {
     })
   }
 
-  pub(crate) fn save(
-    hashes: HashMap<Utf8PathBuf, (Hash, u64)>,
-    manifest: &Manifest,
-    output: &Utf8Path,
-    root: &Utf8Path,
-  ) -> Result<(), Error> {
-    let mut package = BufWriter::new(File::create(output)?);
-
-    package.write_all(super::Package::MAGIC_BYTES.as_bytes())?;
-
-    let paths = hashes
-      .iter()
-      .map(|(path, (hash, _len))| (*hash, path.clone()))
-      .collect::<HashMap<Hash, Utf8PathBuf>>();
-
-    let mut hashes = hashes.values().copied().collect::<Vec<(Hash, u64)>>();
-
-    let manifest = manifest.to_cbor();
-
-    let manifest_hash = Hash::bytes(&manifest);
-
-    hashes.push((manifest_hash, manifest.len().into_u64()));
-
-    hashes.sort_by_key(|hash| *hash.0.as_bytes());
+  pub(crate) fn content(&self, path: &str) -> Option<(Mime, &Content)> {
+    fn index(n: &str) -> Option<usize> {
+      if n.len() > 1 && n.starts_with('0') {
+        return None;
+      }
 
-    hashes.dedup();
+      n.parse().ok()
+    }
 
-    let index = hashes
-      .iter()
-      .position(|(hash, _len)| *hash == manifest_hash)
-      .unwrap()
-      .into_u64();
+    let hash = match &self.manifest.media {
+      Media::Comic { pages } => {
+        if path == "cover.jpg" {
+          self.manifest.thumbnails.as_ref()?.cover
+        } else if let Some(captures) = re::COMIC_THUMBNAIL.captures(path) {
+          *self
+            .manifest
+            .thumbnails
+            .as_ref()?
+            .pages
+            .get(index(&captures[1])?)?
+        } else {
+          *pages.get(index(&re::COMIC_PAGE.captures(path)?[1])?)?
+        }
+      }
+    };
 
-    package.write_u64(index)?;
+    let content = self.files.get(&hash).unwrap();
 
-    package.write_u64(hashes.len().into_u64())?;
+    Some((Self::image_content_type(content), content))
+  }
 
-    for (hash, len) in &hashes {
-      package.write_hash(*hash)?;
-      package.write_u64(*len)?;
-    }
+  pub(crate) fn paths(&self) -> Vec<(String, Hash)> {
+    let mut paths = Vec::new();
 
-    for (hash, _len) in hashes {
-      if hash == manifest_hash {
-        package.write_all(&manifest)?;
-      } else {
-        let path = root.join(paths.get(&hash).unwrap());
+    match &self.manifest.media {
+      Media::Comic { pages } => {
+        if let Some(thumbnails) = &self.manifest.thumbnails {
+          paths.push(("cover.jpg".into(), thumbnails.cover));
 
-        let mut file = File::open(&path).context(FileIo { path: &path })?;
+          for (i, hash) in thumbnails.pages.iter().enumerate() {
+            paths.push((format!("{i}.thumbnail.jpg"), *hash));
+          }
+        }
 
-        io::copy(&mut file, &mut package).context(IoCopy { path: &path })?;
+        for (i, hash) in pages.iter().enumerate() {
+          paths.push((format!("{i}.jpg"), *hash));
+        }
       }
     }
 
-    Ok(())
+    paths
   }
 
   pub(crate) fn file(&self, path: &str) -> Option<(Mime, Vec<u8>)> {
-    match &self.manifest.media {
-      Media::Comic { pages } => {
-        let captures = re::COMIC_PAGE.captures(path)?;
-
-        let n = &captures[1];
-
-        if n.len() > 1 && n.starts_with('0') {
-          return None;
-        }
+    self
+      .content(path)
+      .map(|(content_type, content)| (content_type, content.decompress().into_owned()))
+  }
 
-        Some((
-          mime::IMAGE_JPEG,
-          self
-            .files
-            .get(pages.get(n.parse::<usize>().ok()?)?)
-            .unwrap()
-            .clone(),
-        ))
-      }
-    }
+  fn image_content_type(content: &Content) -> Mime {
+    image::guess_format(&content.head(Self::CONTENT_TYPE_HEAD))
+      .ok()
+      .and_then(|format| format.to_mime_type().parse().ok())
+      .unwrap_or(mime::IMAGE_JPEG)
   }
 
   pub(crate) fn verify(
-    files: &HashMap<Hash, Vec<u8>>,
+    files: &HashMap<Hash, Content>,
     manifest: &Manifest,
     manifest_hash: Hash,
   ) -> Result<(), package::Error> {
     let mut extra = 0u64;
     let mut missing = 0u64;
 
-    let expected: HashSet<Hash> = match &manifest.media {
-      Media::Comic { pages } => pages.iter().copied().collect(),
-    };
+    let expected = manifest.files();
 
     for hash in &expected {
       if !files.contains_key(hash) {
@@ -502,7 +552,10 @@ mod tests {
     let comic = dir.join("comic.package");
 
     subcommand::package::Package {
+      compress: false,
+      optimize: Default::default(),
       root: "tests/packages/comic".into(),
+      thumbnails: false,
       output: comic.clone(),
     }
     .run()
@@ -515,43 +568,244 @@ mod tests {
   }
 
   #[test]
-  fn save_and_load() {
+  fn paths() {
+    let dir = tempdir();
+
+    let comic = dir.join("comic.package");
+
+    subcommand::package::Package {
+      compress: false,
+      optimize: Default::default(),
+      root: "tests/packages/comic".into(),
+      thumbnails: true,
+      output: comic.clone(),
+    }
+    .run()
+    .unwrap();
+
+    let comic = Package::load(&comic).unwrap();
+
+    let paths = comic.paths();
+
+    assert_eq!(
+      paths
+        .iter()
+        .map(|(path, _hash)| path.as_str())
+        .collect::<Vec<&str>>(),
+      [
+        "cover.jpg",
+        "0.thumbnail.jpg",
+        "1.thumbnail.jpg",
+        "2.thumbnail.jpg",
+        "0.jpg",
+        "1.jpg",
+        "2.jpg",
+      ],
+    );
+
+    for (path, hash) in paths {
+      assert_eq!(comic.content(&path).unwrap().1, &comic.files[&hash]);
+    }
+  }
+
+  #[test]
+  fn image_content_type_is_detected() {
+    let dir = tempdir();
+
+    let comic = dir.join("comic.package");
+
+    subcommand::package::Package {
+      compress: false,
+      optimize: subcommand::package::optimize::Optimize {
+        format: Some(subcommand::package::optimize::Format::Webp),
+        ..Default::default()
+      },
+      root: "tests/packages/comic".into(),
+      thumbnails: false,
+      output: comic.clone(),
+    }
+    .run()
+    .unwrap();
+
+    let comic = Package::load(&comic).unwrap();
+
+    assert_eq!(comic.file("0.jpg").unwrap().0, "image/webp");
+  }
+
+  #[test]
+  fn compressed_files_are_decompressed() {
+    let tempdir = tempdir();
+
+    let output = tempdir.join("package.package");
+
+    let page = "PAGE".repeat(100);
+
+    let mut writer = Writer::new(true).unwrap();
+
+    let (hash, len) = writer.add(page.as_bytes()).unwrap();
+
+    assert_eq!(hash, Hash::bytes(page.as_bytes()));
+    assert_eq!(len, page.len().into_u64());
+
+    let manifest = Manifest {
+      name: "Foo".into(),
+      media: Media::Comic { pages: vec![hash] },
+      thumbnails: None,
+    };
+
+    writer.finish(&manifest, &output).unwrap();
+
+    assert!(fs::metadata(&output).unwrap().len() < page.len().into_u64());
+
+    let package = Package::load(&output).unwrap();
+
+    assert!(package.files[&hash].compressed);
+    assert!(package.files[&hash].data.len() < page.len());
+    assert_eq!(package.file("0.jpg").unwrap().1, page.as_bytes());
+    assert_eq!(package.manifest, manifest);
+  }
+
+  #[test]
+  fn incompressible_files_are_stored_raw() {
     let tempdir = tempdir();
 
     let output = tempdir.join("package.package");
 
-    let root = tempdir.join("root");
+    let mut writer = Writer::new(true).unwrap();
+
+    let (hash, _len) = writer.add(b"A".as_slice()).unwrap();
+
+    let manifest = Manifest {
+      name: "Foo".into(),
+      media: Media::Comic { pages: vec![hash] },
+      thumbnails: None,
+    };
+
+    writer.finish(&manifest, &output).unwrap();
+
+    let package = Package::load(&output).unwrap();
+
+    assert!(!package.files[&hash].compressed);
+    assert_eq!(package.files[&hash].data, b"A");
+  }
+
+  #[test]
+  fn duplicate_files_are_stored_once() {
+    let tempdir = tempdir();
+
+    let output = tempdir.join("package.package");
+
+    let page = "PAGE".repeat(100);
+
+    let mut writer = Writer::new(true).unwrap();
+
+    let (foo, _len) = writer.add(page.as_bytes()).unwrap();
+    let (bar, _len) = writer.add(b"bar".as_slice()).unwrap();
+    assert_eq!(writer.add(page.as_bytes()).unwrap().0, foo);
+    assert_eq!(writer.add(b"bar".as_slice()).unwrap().0, bar);
+
+    let manifest = Manifest {
+      name: "Foo".into(),
+      media: Media::Comic {
+        pages: vec![foo, bar, foo, bar],
+      },
+      thumbnails: None,
+    };
 
-    tempdir.write("root/0.jpg", "PAGE0");
-    tempdir.write("root/1.jpg", "PAGE1");
+    writer.finish(&manifest, &output).unwrap();
+
+    let package = Package::load(&output).unwrap();
+
+    assert_eq!(package.files.len(), 3);
+    assert_eq!(package.file("2.jpg").unwrap().1, page.as_bytes());
+    assert_eq!(package.file("3.jpg").unwrap().1, b"bar");
+  }
+
+  #[test]
+  fn compressed_file_hash_invalid() {
+    let tempdir = tempdir();
+
+    let package = tempdir.join("package.package");
+
+    let data = zstd::encode_all(b"foo".as_slice(), 0).unwrap();
+
+    let mut bytes = Vec::new();
+
+    bytes.extend_from_slice(Package::MAGIC_BYTES.as_bytes());
+    bytes.extend_from_slice(&0u64.to_le_bytes());
+    bytes.extend_from_slice(&1u64.to_le_bytes());
+    bytes.extend_from_slice(Hash::bytes(&data).as_bytes());
+    bytes.extend_from_slice(&(data.len().into_u64() | Package::COMPRESSED).to_le_bytes());
+    bytes.extend_from_slice(&data);
+
+    fs::write(&package, bytes).unwrap();
+
+    assert_matches!(
+      Package::load(&package).unwrap_err(),
+      Error::FileHashInvalid { actual, .. }
+      if actual == Hash::bytes(b"foo"),
+    );
+  }
+
+  #[test]
+  fn compressed_file_decompress_error() {
+    let tempdir = tempdir();
+
+    let package = tempdir.join("package.package");
+
+    let mut bytes = Vec::new();
+
+    bytes.extend_from_slice(Package::MAGIC_BYTES.as_bytes());
+    bytes.extend_from_slice(&0u64.to_le_bytes());
+    bytes.extend_from_slice(&1u64.to_le_bytes());
+    bytes.extend_from_slice(Hash::bytes(b"foo").as_bytes());
+    bytes.extend_from_slice(&(3 | Package::COMPRESSED).to_le_bytes());
+    bytes.extend_from_slice(b"foo");
+
+    fs::write(&package, bytes).unwrap();
+
+    assert_matches!(
+      Package::load(&package).unwrap_err(),
+      Error::Decompress { hash, .. }
+      if hash == Hash::bytes(b"foo"),
+    );
+  }
+
+  #[test]
+  fn save_and_load() {
+    let tempdir = tempdir();
+
+    let output = tempdir.join("package.package");
 
     let page0 = Hash::bytes(b"PAGE0");
     let page1 = Hash::bytes(b"PAGE1");
 
+    let mut writer = Writer::new(false).unwrap();
+
+    assert_eq!(writer.add(b"PAGE0".as_slice()).unwrap(), (page0, 5));
+    assert_eq!(writer.add(b"PAGE1".as_slice()).unwrap(), (page1, 5));
+
     let manifest = Manifest {
       name: "Foo".into(),
       media: Media::Comic {
         pages: vec![page0, page1],
       },
+      thumbnails: None,
     };
 
     let manifest_bytes = manifest.to_cbor();
 
     let hash = Hash::bytes(&manifest_bytes);
 
-    let hashes = vec![("0.jpg".into(), (page0, 5)), ("1.jpg".into(), (page1, 5))]
-      .into_iter()
-      .collect();
-
-    Package::save(hashes, &manifest, &output, &root).unwrap();
+    assert_eq!(writer.finish(&manifest, &output).unwrap(), hash);
 
     assert_eq!(
       Package::load(&output).unwrap(),
       Package {
         files: vec![
-          (page0, b"PAGE0".into()),
-          (page1, b"PAGE1".into()),
-          (hash, manifest_bytes)
+          (page0, b"PAGE0".to_vec().into()),
+          (page1, b"PAGE1".to_vec().into()),
+          (hash, manifest_bytes.into())
         ]
         .into_iter()
         .collect(),
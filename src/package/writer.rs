@@ -0,0 +1,201 @@
+use super::*;
+
+struct Spool {
+  file: BufWriter<File>,
+  len: u64,
+}
+
+impl Spool {
+  fn new() -> io::Result<Self> {
+    Ok(Self {
+      file: BufWriter::new(tempfile::tempfile()?),
+      len: 0,
+    })
+  }
+
+  fn copy(&mut self, start: u64, len: u64, output: &mut impl Write) -> io::Result<()> {
+    self.file.flush()?;
+
+    let file = self.file.get_mut();
+
+    file.seek(io::SeekFrom::Start(start))?;
+
+    let copied = io::copy(&mut file.take(len), output)?;
+
+    if copied != len {
+      return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+
+    Ok(())
+  }
+
+  fn truncate(&mut self, len: u64) -> io::Result<()> {
+    self.file.flush()?;
+    self.file.get_ref().set_len(len)?;
+    self.file.seek(io::SeekFrom::Start(len))?;
+    self.len = len;
+    Ok(())
+  }
+}
+
+impl Write for Spool {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    let n = self.file.write(buf)?;
+    self.len += n.into_u64();
+    Ok(n)
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    self.file.flush()
+  }
+}
+
+#[derive(Clone, Copy)]
+struct Entry {
+  compressed: bool,
+  len: u64,
+  start: u64,
+}
+
+pub(crate) struct Writer {
+  compressed: Option<Spool>,
+  entries: BTreeMap<Hash, Entry>,
+  raw: Spool,
+}
+
+impl Writer {
+  const BUFFER_SIZE: usize = 64 * 1024;
+
+  pub(crate) fn new(compress: bool) -> Result<Self, Error> {
+    Ok(Self {
+      compressed: if compress {
+        Some(Spool::new().context(SpoolIo)?)
+      } else {
+        None
+      },
+      entries: BTreeMap::new(),
+      raw: Spool::new().context(SpoolIo)?,
+    })
+  }
+
+  pub(crate) fn add(&mut self, mut reader: impl Read) -> Result<(Hash, u64), Error> {
+    let raw_start = self.raw.len;
+
+    let compressed_start = self.compressed.as_ref().map_or(0, |spool| spool.len);
+
+    let mut hasher = blake3::Hasher::new();
+
+    let mut encoder = match &mut self.compressed {
+      Some(spool) => Some(zstd::Encoder::new(spool, Package::COMPRESSION_LEVEL).context(SpoolIo)?),
+      None => None,
+    };
+
+    let mut buffer = vec![0; Self::BUFFER_SIZE];
+
+    loop {
+      let n = match reader.read(&mut buffer) {
+        Ok(0) => break,
+        Ok(n) => n,
+        Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+        Err(source) => return Err(source).context(ReadInput),
+      };
+
+      hasher.update(&buffer[..n]);
+
+      self.raw.write_all(&buffer[..n]).context(SpoolIo)?;
+
+      if let Some(encoder) = &mut encoder {
+        encoder.write_all(&buffer[..n]).context(SpoolIo)?;
+      }
+    }
+
+    if let Some(encoder) = encoder {
+      encoder.finish().context(SpoolIo)?;
+    }
+
+    let hash = Hash::from(*hasher.finalize().as_bytes());
+
+    let len = self.raw.len - raw_start;
+
+    if self.entries.contains_key(&hash) {
+      self.raw.truncate(raw_start).context(SpoolIo)?;
+
+      if let Some(spool) = &mut self.compressed {
+        spool.truncate(compressed_start).context(SpoolIo)?;
+      }
+
+      return Ok((hash, len));
+    }
+
+    let mut entry = Entry {
+      compressed: false,
+      len,
+      start: raw_start,
+    };
+
+    if let Some(spool) = &mut self.compressed {
+      let compressed = spool.len - compressed_start;
+
+      if compressed < len {
+        self.raw.truncate(raw_start).context(SpoolIo)?;
+
+        entry = Entry {
+          compressed: true,
+          len: compressed,
+          start: compressed_start,
+        };
+      } else {
+        spool.truncate(compressed_start).context(SpoolIo)?;
+      }
+    }
+
+    self.entries.insert(hash, entry);
+
+    Ok((hash, len))
+  }
+
+  pub(crate) fn finish(mut self, manifest: &Manifest, output: &Utf8Path) -> Result<Hash, Error> {
+    let (manifest, _len) = self.add(manifest.to_cbor().as_slice())?;
+
+    let index = self
+      .entries
+      .keys()
+      .position(|hash| *hash == manifest)
+      .unwrap()
+      .into_u64();
+
+    let mut package = BufWriter::new(File::create(output)?);
+
+    package.write_all(Package::MAGIC_BYTES.as_bytes())?;
+
+    package.write_u64(index)?;
+
+    package.write_u64(self.entries.len().into_u64())?;
+
+    for (hash, entry) in &self.entries {
+      package.write_hash(*hash)?;
+
+      package.write_u64(if entry.compressed {
+        entry.len | Package::COMPRESSED
+      } else {
+        entry.len
+      })?;
+    }
+
+    for entry in self.entries.values() {
+      let spool = if entry.compressed {
+        self.compressed.as_mut().unwrap()
+      } else {
+        &mut self.raw
+      };
+
+      spool
+        .copy(entry.start, entry.len, &mut package)
+        .context(SpoolIo)?;
+    }
+
+    package.flush()?;
+
+    Ok(manifest)
+  }
+}
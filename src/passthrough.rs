@@ -6,14 +6,25 @@ use {
       self, AeadKey, CryptoError, ExportKeyingMaterialError, HandshakeTokenKey, HeaderKey, KeyPair,
       Keys, PacketKey, UnsupportedVersion,
     },
-    ConnectError, Endpoint,
+    ConnectError, Endpoint, TransportConfig,
+  },
+  quinn_proto::{
+    transport_parameters::TransportParameters, ConnectionId, Side, TransportError,
+    TransportErrorCode,
   },
-  quinn_proto::{transport_parameters::TransportParameters, ConnectionId, Side, TransportError},
 };
 
+const IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+// keep NAT mappings open for connections to rendezvous peers and relays
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(15);
+
 struct Key;
 
 impl Key {
+  // quinn relies on the tag to pad short packets, such as initial closes,
+  // to the minimum length required for header protection sampling
+  const TAG_LEN: usize = 16;
+
   fn keys() -> Keys {
     Keys {
       header: KeyPair {
@@ -53,8 +64,10 @@ impl HeaderKey for Key {
 
   fn encrypt(&self, _pn_offset: usize, _packet: &mut [u8]) {}
 
+  // same as AES and ChaCha20 header protection, so that packets quinn pads
+  // for sampling, such as those refusing connections, are long enough
   fn sample_size(&self) -> usize {
-    32
+    16
   }
 }
 
@@ -67,8 +80,13 @@ impl PacketKey for Key {
     &self,
     _packet: u64,
     _header: &[u8],
-    _payload: &mut BytesMut,
+    payload: &mut BytesMut,
   ) -> Result<(), CryptoError> {
+    let len = payload
+      .len()
+      .checked_sub(Self::TAG_LEN)
+      .ok_or(CryptoError)?;
+    payload.truncate(len);
     Ok(())
   }
 
@@ -79,12 +97,13 @@ impl PacketKey for Key {
   }
 
   fn tag_len(&self) -> usize {
-    0
+    Self::TAG_LEN
   }
 }
 
 struct ClientConfig {
   id: Id,
+  network_key: Option<NetworkKey>,
 }
 
 impl crypto::ClientConfig for ClientConfig {
@@ -96,6 +115,7 @@ impl crypto::ClientConfig for ClientConfig {
   ) -> Result<Box<dyn crypto::Session>, ConnectError> {
     Ok(Box::new(Session::new(
       self.id,
+      self.network_key,
       Some(server_name.parse::<Id>().unwrap()),
       Side::Client,
       params,
@@ -105,6 +125,7 @@ impl crypto::ClientConfig for ClientConfig {
 
 struct ServerConfig {
   id: Id,
+  network_key: Option<NetworkKey>,
 }
 
 impl crypto::ServerConfig for ServerConfig {
@@ -134,7 +155,13 @@ impl crypto::ServerConfig for ServerConfig {
     _version: u32,
     params: &TransportParameters,
   ) -> Box<dyn crypto::Session> {
-    Box::new(Session::new(self.id, None, Side::Server, params))
+    Box::new(Session::new(
+      self.id,
+      self.network_key,
+      None,
+      Side::Server,
+      params,
+    ))
   }
 }
 
@@ -144,45 +171,160 @@ enum State {
   ZeroRtt,
   Handshake,
   OneRtt,
+  Finish,
   Data,
 }
 
+// the handshake proves network key membership with tags bound to both
+// sides' nonces, so they can't be replayed on other connections, but
+// packets are neither encrypted nor authenticated, so anyone on the path
+// can read and modify traffic
 pub(crate) struct Session {
   id: Id,
+  member: bool,
+  network_key: Option<NetworkKey>,
+  nonce: [u8; Session::NONCE_LEN],
   params: TransportParameters,
   remote_id: Option<Id>,
+  remote_nonce: Option<[u8; Session::NONCE_LEN]>,
   remote_params: Option<TransportParameters>,
   side: Side,
   state: State,
 }
 
 impl Session {
-  fn new(id: Id, remote_id: Option<Id>, side: Side, params: &TransportParameters) -> Self {
+  const CLIENT_FINISH: &'static str = "client finish";
+  const CLIENT_HELLO: &'static str = "client hello";
+  const NONCE_LEN: usize = 16;
+  const SERVER_HELLO: &'static str = "server hello";
+
+  fn new(
+    id: Id,
+    network_key: Option<NetworkKey>,
+    remote_id: Option<Id>,
+    side: Side,
+    params: &TransportParameters,
+  ) -> Self {
     Self {
       id,
+      member: false,
+      network_key,
+      nonce: rand::random(),
       params: *params,
       remote_id,
+      remote_nonce: None,
       remote_params: None,
       side,
       state: State::Initial,
     }
   }
 
-  pub(crate) fn endpoint(id: Id, address: IpAddr, port: u16) -> Endpoint {
-    let mut endpoint = Endpoint::server(
-      quinn::ServerConfig::new(Arc::new(ServerConfig { id }), Arc::new(Key)),
-      (address, port).into(),
-    )
-    .unwrap();
+  pub(crate) fn endpoint(
+    id: Id,
+    network_key: Option<NetworkKey>,
+    address: IpAddr,
+    port: u16,
+    max_streams: u32,
+  ) -> Endpoint {
+    let mut server =
+      quinn::ServerConfig::new(Arc::new(ServerConfig { id, network_key }), Arc::new(Key));
+    server.transport_config(Arc::new(Self::transport(max_streams)));
+
+    let mut endpoint = Endpoint::server(server, (address, port).into()).unwrap();
 
-    endpoint.set_default_client_config(quinn::ClientConfig::new(Arc::new(ClientConfig { id })));
+    endpoint.set_default_client_config(Self::client(id, network_key, Self::transport(max_streams)));
 
     endpoint
   }
 
+  // config for dialing rendezvous peers and relays, whose connections must
+  // stay open while idle so that they can reach us through NAT
+  pub(crate) fn keep_alive(
+    id: Id,
+    network_key: Option<NetworkKey>,
+    max_streams: u32,
+  ) -> quinn::ClientConfig {
+    let mut transport = Self::transport(max_streams);
+    transport.keep_alive_interval(Some(KEEP_ALIVE_INTERVAL));
+    Self::client(id, network_key, transport)
+  }
+
+  fn client(
+    id: Id,
+    network_key: Option<NetworkKey>,
+    transport: TransportConfig,
+  ) -> quinn::ClientConfig {
+    let mut client = quinn::ClientConfig::new(Arc::new(ClientConfig { id, network_key }));
+    client.transport_config(Arc::new(transport));
+    client
+  }
+
+  fn transport(max_streams: u32) -> TransportConfig {
+    let mut transport = TransportConfig::default();
+    transport.max_concurrent_bidi_streams(max_streams.into());
+    transport.max_idle_timeout(Some(IDLE_TIMEOUT.try_into().unwrap()));
+    transport
+  }
+
   pub(crate) fn peer_identity(connection: &Connection) -> Id {
     *connection.peer_identity().unwrap().downcast().unwrap()
   }
+
+  pub(crate) fn is_member(connection: &Connection) -> bool {
+    connection
+      .handshake_data()
+      .and_then(|data| data.downcast::<bool>().ok())
+      .is_some_and(|member| *member)
+  }
+
+  // tags cover both ids and the client's nonce, and once the server has
+  // replied, the server's nonce as well
+  fn tag(&self, label: &str) -> [u8; Hash::LEN] {
+    let Some(key) = self.network_key else {
+      return [0; Hash::LEN];
+    };
+
+    let remote_id = self.remote_id.unwrap();
+
+    let (ids, client_nonce, server_nonce) = match self.side {
+      Side::Client => ([self.id, remote_id], Some(self.nonce), self.remote_nonce),
+      Side::Server => ([remote_id, self.id], self.remote_nonce, Some(self.nonce)),
+    };
+
+    let mut nonces = client_nonce.unwrap().to_vec();
+
+    if label != Self::CLIENT_HELLO {
+      nonces.extend_from_slice(&server_nonce.unwrap());
+    }
+
+    *key.mac(label, &ids, &nonces).as_bytes()
+  }
+
+  fn verify(&self, label: &str, tag: &[u8]) -> Result<(), TransportError> {
+    if self.network_key.is_some() && self.tag(label)[..] != *tag {
+      return Err(Self::refuse("network key mismatch"));
+    }
+
+    Ok(())
+  }
+
+  fn write_hello(&self, buf: &mut Vec<u8>) {
+    buf.extend_from_slice(self.id.as_bytes());
+    buf.extend_from_slice(&self.nonce);
+    buf.extend_from_slice(&self.tag(match self.side {
+      Side::Client => Self::CLIENT_HELLO,
+      Side::Server => Self::SERVER_HELLO,
+    }));
+    self.params.write(buf);
+  }
+
+  fn refuse(reason: &str) -> TransportError {
+    TransportError {
+      code: TransportErrorCode::CONNECTION_REFUSED,
+      frame: None,
+      reason: reason.into(),
+    }
+  }
 }
 
 impl crypto::Session for Session {
@@ -204,7 +346,7 @@ impl crypto::Session for Session {
   }
 
   fn handshake_data(&self) -> Option<Box<dyn Any>> {
-    unimplemented!()
+    Some(Box::new(self.member))
   }
 
   fn initial_keys(&self, _dst_cid: &ConnectionId, _side: Side) -> Keys {
@@ -235,15 +377,38 @@ impl crypto::Session for Session {
   }
 
   fn read_handshake(&mut self, buf: &[u8]) -> Result<bool, TransportError> {
-    let array: [u8; Id::LEN] = buf[..Id::LEN].try_into().unwrap();
-    let remote_id = Id::from(array);
-    if let Some(expected_id) = self.remote_id {
-      assert_eq!(remote_id, expected_id);
-    } else {
-      self.remote_id = Some(remote_id);
+    // the client's finish proves that it saw our nonce, and so is not a
+    // replayed hello
+    if self.state == State::Finish {
+      self.verify(Self::CLIENT_FINISH, buf)?;
+      self.member = self.network_key.is_some();
+      self.state = State::Data;
+      return Ok(false);
+    }
+    if buf.len() < Id::LEN + Self::NONCE_LEN + Hash::LEN {
+      return Err(Self::refuse("handshake too short"));
+    }
+    let (id, rest) = buf.split_at(Id::LEN);
+    let (nonce, rest) = rest.split_at(Self::NONCE_LEN);
+    let (tag, params) = rest.split_at(Hash::LEN);
+    let array: [u8; Id::LEN] = id.try_into().unwrap();
+    // record the id the remote actually presented, even if we expected
+    // another, so that the node can report the mismatch and close the
+    // connection itself
+    self.remote_id = Some(Id::from(array));
+    self.remote_nonce = Some(nonce.try_into().unwrap());
+    match self.side {
+      Side::Client => {
+        self.verify(Self::SERVER_HELLO, tag)?;
+        self.member = self.network_key.is_some();
+      }
+      // membership is only granted once the client's finish is verified
+      Side::Server => self.verify(Self::CLIENT_HELLO, tag)?,
     }
-    self.remote_params =
-      Some(TransportParameters::read(self.side, &mut Cursor::new(&buf[Id::LEN..])).unwrap());
+    self.remote_params = Some(
+      TransportParameters::read(self.side, &mut Cursor::new(params))
+        .map_err(|_| Self::refuse("invalid transport parameters"))?,
+    );
     match (self.state, self.side) {
       (State::Initial, Side::Server) => {
         self.state = State::ZeroRtt;
@@ -268,8 +433,7 @@ impl crypto::Session for Session {
   fn write_handshake(&mut self, buf: &mut Vec<u8>) -> Option<Keys> {
     match (self.state, self.side) {
       (State::Initial, Side::Client) => {
-        buf.extend_from_slice(self.id.as_bytes());
-        self.params.write(buf);
+        self.write_hello(buf);
         self.state = State::ZeroRtt;
         None
       }
@@ -278,12 +442,12 @@ impl crypto::Session for Session {
         Some(Key::keys())
       }
       (State::Handshake, Side::Server) => {
-        buf.extend_from_slice(self.id.as_bytes());
-        self.params.write(buf);
-        self.state = State::Data;
+        self.write_hello(buf);
+        self.state = State::Finish;
         Some(Key::keys())
       }
-      (State::OneRtt, _) => {
+      (State::OneRtt, Side::Client) => {
+        buf.extend_from_slice(&self.tag(Self::CLIENT_FINISH));
         self.state = State::Data;
         Some(Key::keys())
       }
@@ -291,3 +455,51 @@ impl crypto::Session for Session {
     }
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use {super::*, crypto::Session as _};
+
+  fn session(id: Id, remote_id: Option<Id>, side: Side) -> Session {
+    let params = TransportParameters::read(side, &mut Cursor::new(&[][..])).unwrap();
+    Session::new(id, Some("foo".parse().unwrap()), remote_id, side, &params)
+  }
+
+  #[test]
+  fn replayed_handshake_is_refused() {
+    let client_id = Id::from([1; Id::LEN]);
+    let server_id = Id::from([2; Id::LEN]);
+
+    let mut client = session(client_id, Some(server_id), Side::Client);
+    let mut server = session(server_id, None, Side::Server);
+
+    let mut hello = Vec::new();
+    client.write_handshake(&mut hello);
+    client.write_handshake(&mut Vec::new());
+
+    server.read_handshake(&hello).unwrap();
+    server.write_handshake(&mut Vec::new());
+
+    let mut reply = Vec::new();
+    server.write_handshake(&mut reply);
+
+    client.read_handshake(&reply).unwrap();
+    assert!(client.member);
+
+    let mut finish = Vec::new();
+    client.write_handshake(&mut finish);
+    assert!(!server.member);
+
+    server.read_handshake(&finish).unwrap();
+    assert!(server.member);
+    assert!(!server.is_handshaking());
+
+    let mut replayed = session(server_id, None, Side::Server);
+    replayed.read_handshake(&hello).unwrap();
+    replayed.write_handshake(&mut Vec::new());
+    replayed.write_handshake(&mut Vec::new());
+
+    assert!(replayed.read_handshake(&finish).is_err());
+    assert!(!replayed.member);
+  }
+}
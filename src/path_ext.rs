@@ -11,7 +11,6 @@ impl PathExt for Path {
 }
 
 pub(crate) trait PathBufExt {
-  #[allow(unused)]
   fn try_into_utf8(self) -> Result<Utf8PathBuf>;
 }
 
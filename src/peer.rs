@@ -22,11 +22,32 @@ pub(crate) struct Peer {
   pub(crate) id: Id,
   pub(crate) ip: IpAddr,
   pub(crate) port: u16,
+  #[serde(default, skip_serializing_if = "is_zero")]
+  pub(crate) scope_id: u32,
+}
+
+fn is_zero(n: &u32) -> bool {
+  *n == 0
 }
 
 impl Peer {
+  pub(crate) fn new(id: Id, socket_addr: SocketAddr) -> Self {
+    Self {
+      id,
+      ip: socket_addr.ip(),
+      port: socket_addr.port(),
+      scope_id: match socket_addr {
+        SocketAddr::V4(_) => 0,
+        SocketAddr::V6(socket_addr) => socket_addr.scope_id(),
+      },
+    }
+  }
+
   pub(crate) fn socket_addr(self) -> SocketAddr {
-    (self.ip, self.port).into()
+    match self.ip {
+      IpAddr::V4(ip) => (ip, self.port).into(),
+      IpAddr::V6(ip) => SocketAddrV6::new(ip, self.port, 0, self.scope_id).into(),
+    }
   }
 }
 
@@ -48,11 +69,10 @@ impl FromStr for Peer {
       .parse::<SocketAddr>()
       .context(AddressError { input })?;
 
-    Ok(Self {
-      id: captures[1].parse().context(IdError { input })?,
-      ip: socket_addr.ip(),
-      port: socket_addr.port(),
-    })
+    Ok(Self::new(
+      captures[1].parse().context(IdError { input })?,
+      socket_addr,
+    ))
   }
 }
 
@@ -79,6 +99,7 @@ mod tests {
           .parse()
           .unwrap(),
         ip: Ipv4Addr::new(1, 2, 3, 4).into(),
+        scope_id: 0,
       },
     );
 
@@ -90,6 +111,19 @@ mod tests {
           .parse()
           .unwrap(),
         ip: Ipv6Addr::new(1, 2, 3, 4, 5, 6, 7, 8).into(),
+        scope_id: 0,
+      },
+    );
+
+    case(
+      "0f89ce0b671f7277b105035ea88341a81c5fceaed092eab29721fe6f86807133@[fe80::1%3]:5",
+      Peer {
+        port: 5,
+        id: "0f89ce0b671f7277b105035ea88341a81c5fceaed092eab29721fe6f86807133"
+          .parse()
+          .unwrap(),
+        ip: Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 1).into(),
+        scope_id: 3,
       },
     );
   }
@@ -102,6 +136,7 @@ mod tests {
         .parse()
         .unwrap(),
       ip: Ipv4Addr::new(1, 2, 3, 4).into(),
+      scope_id: 0,
     };
 
     let cbor = peer.to_cbor();
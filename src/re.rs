@@ -1,3 +1,11 @@
 use super::*;
 
 pub(crate) static COMIC_PAGE: Lazy<Regex> = lazy_regex!(r"^(\d+)\.jpg$");
+pub(crate) static COMIC_THUMBNAIL: Lazy<Regex> = lazy_regex!(r"^(\d+)\.thumbnail\.jpg$");
+pub(crate) static NUMERIC_PAGE: Lazy<Regex> = Lazy::new(|| {
+  Regex::new(&format!(
+    r"^(\d+)\.(?i:{})$",
+    Metadata::PAGE_EXTENSIONS.join("|")
+  ))
+  .unwrap()
+});
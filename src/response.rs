@@ -1,10 +1,28 @@
 use super::*;
 
+#[derive(Deserialize, Serialize)]
+pub(crate) struct File(pub(crate) Option<u64>);
+
 #[derive(Deserialize, Serialize)]
 pub(crate) struct Get(pub(crate) Option<Vec<u8>>);
 
+#[derive(Deserialize, Serialize)]
+pub(crate) struct Introduce(pub(crate) Option<Peer>);
+
+#[derive(Deserialize, Serialize)]
+pub(crate) struct Observe(pub(crate) SocketAddr);
+
 #[derive(Deserialize, Serialize)]
 pub(crate) struct Ping;
 
+#[derive(Deserialize, Serialize)]
+pub(crate) struct Punch;
+
+#[derive(Deserialize, Serialize)]
+pub(crate) struct Query(pub(crate) Vec<Hash>);
+
+#[derive(Deserialize, Serialize)]
+pub(crate) struct Relay(pub(crate) bool);
+
 #[derive(Deserialize, Serialize)]
 pub(crate) struct Search(pub(crate) Vec<Hash>);
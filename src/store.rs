@@ -0,0 +1,255 @@
+use super::*;
+
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct Collected {
+  pub(crate) blobs: u64,
+  pub(crate) bytes: u64,
+}
+
+pub(crate) struct Store {
+  dir: Utf8PathBuf,
+}
+
+impl Store {
+  pub(crate) fn new(data_dir: &Utf8Path) -> Self {
+    Self {
+      dir: data_dir.into(),
+    }
+  }
+
+  fn blobs(&self) -> Utf8PathBuf {
+    self.dir.join("blobs")
+  }
+
+  fn blob(&self, hash: Hash) -> Utf8PathBuf {
+    self.blobs().join(hash.to_string())
+  }
+
+  fn pins(&self) -> Utf8PathBuf {
+    self.dir.join("pins")
+  }
+
+  pub(crate) fn contains(&self, hash: Hash) -> bool {
+    self.blob(hash).is_file()
+  }
+
+  pub(crate) fn get(&self, hash: Hash) -> Result<Option<Vec<u8>>> {
+    let path = self.blob(hash);
+
+    match fs::read(&path) {
+      Ok(content) => Ok(Some(content)),
+      Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+      Err(source) => Err(error::Io { path }.into_error(source)),
+    }
+  }
+
+  pub(crate) fn insert(&self, content: &[u8]) -> Result<Hash> {
+    let hash = Hash::bytes(content);
+
+    if self.contains(hash) {
+      return Ok(hash);
+    }
+
+    let dir = self.blobs();
+
+    fs::create_dir_all(&dir).context(error::Io { path: &dir })?;
+
+    let mut file = tempfile::NamedTempFile::new_in(&dir).context(error::Io { path: &dir })?;
+
+    let path = self.blob(hash);
+
+    file.write_all(content).context(error::Io { path: &path })?;
+
+    file
+      .persist(&path)
+      .map_err(|err| err.error)
+      .context(error::Io { path: &path })?;
+
+    Ok(hash)
+  }
+
+  pub(crate) fn manifest(&self, hash: Hash) -> Result<Option<Manifest>> {
+    let Some(cbor) = self.get(hash)? else {
+      return Ok(None);
+    };
+
+    Manifest::from_cbor(&cbor)
+      .context(error::StoreManifest { hash })
+      .map(Some)
+  }
+
+  pub(crate) fn package(&self, hash: Hash) -> Result<Package> {
+    let manifest = self.manifest(hash)?.context(error::StoreMissing { hash })?;
+
+    let mut files = HashMap::new();
+
+    for hash in manifest.files().into_iter().chain([hash]) {
+      let content = self.get(hash)?.context(error::StoreMissing { hash })?;
+      files.insert(hash, package::Content::from(content));
+    }
+
+    Package::verify(&files, &manifest, hash).context(error::StorePackage { hash })?;
+
+    Ok(Package {
+      files,
+      hash,
+      manifest,
+    })
+  }
+
+  pub(crate) fn pin(&self, hash: Hash) -> Result {
+    let dir = self.pins();
+    fs::create_dir_all(&dir).context(error::Io { path: &dir })?;
+    let path = dir.join(hash.to_string());
+    fs::write(&path, []).context(error::Io { path })
+  }
+
+  pub(crate) fn pinned(&self) -> Result<BTreeSet<Hash>> {
+    Self::hashes(&self.pins())
+  }
+
+  pub(crate) fn unpin(&self, hash: Hash) -> Result<bool> {
+    let path = self.pins().join(hash.to_string());
+
+    match fs::remove_file(&path) {
+      Ok(()) => Ok(true),
+      Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(false),
+      Err(source) => Err(error::Io { path }.into_error(source)),
+    }
+  }
+
+  pub(crate) fn gc(&self) -> Result<Collected> {
+    let mut referenced = HashSet::new();
+
+    for root in self.pinned()? {
+      referenced.insert(root);
+
+      if let Some(manifest) = self.manifest(root)? {
+        referenced.extend(manifest.files());
+      }
+    }
+
+    let mut collected = Collected::default();
+
+    for hash in Self::hashes(&self.blobs())? {
+      if referenced.contains(&hash) {
+        continue;
+      }
+
+      let path = self.blob(hash);
+
+      let len = path.metadata().map(|metadata| metadata.len()).unwrap_or(0);
+
+      fs::remove_file(&path).context(error::Io { path })?;
+
+      collected.blobs += 1;
+      collected.bytes += len;
+    }
+
+    Ok(collected)
+  }
+
+  fn hashes(dir: &Utf8Path) -> Result<BTreeSet<Hash>> {
+    let entries = match fs::read_dir(dir) {
+      Ok(entries) => entries,
+      Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(BTreeSet::new()),
+      Err(source) => return Err(error::Io { path: dir }.into_error(source)),
+    };
+
+    let mut hashes = BTreeSet::new();
+
+    for entry in entries {
+      let entry = entry.context(error::Io { path: dir })?;
+
+      if let Some(hash) = entry
+        .file_name()
+        .to_str()
+        .and_then(|name| name.parse::<Hash>().ok())
+      {
+        hashes.insert(hash);
+      }
+    }
+
+    Ok(hashes)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn package(tempdir: &TempDir, root: &str) -> Package {
+    let output = tempdir.join("package.package");
+
+    subcommand::package::Package {
+      compress: true,
+      optimize: Default::default(),
+      root: root.into(),
+      output: output.clone(),
+      thumbnails: false,
+    }
+    .run()
+    .unwrap();
+
+    Package::load(&output).unwrap()
+  }
+
+  #[test]
+  fn insert_and_get() {
+    let tempdir = tempdir();
+
+    let store = Store::new(tempdir.path_utf8());
+
+    let hash = Hash::bytes(b"foo");
+
+    assert!(!store.contains(hash));
+    assert_eq!(store.get(hash).unwrap(), None);
+
+    assert_eq!(store.insert(b"foo").unwrap(), hash);
+    assert_eq!(store.insert(b"foo").unwrap(), hash);
+
+    assert!(store.contains(hash));
+    assert_eq!(store.get(hash).unwrap().unwrap(), b"foo");
+
+    assert_eq!(fs::read_dir(tempdir.join("blobs")).unwrap().count(), 1);
+  }
+
+  #[test]
+  fn gc() {
+    let tempdir = tempdir();
+
+    let package = package(&tempdir, "tests/packages/comic");
+
+    let store = Store::new(&tempdir.join("data"));
+
+    for content in package.files.values() {
+      store.insert(&content.decompress()).unwrap();
+    }
+
+    let orphan = store.insert(b"orphan").unwrap();
+
+    store.pin(package.hash).unwrap();
+
+    assert_eq!(store.pinned().unwrap(), [package.hash].into());
+
+    assert_eq!(store.gc().unwrap(), Collected { blobs: 1, bytes: 6 });
+
+    assert!(!store.contains(orphan));
+
+    for hash in package.files.keys() {
+      assert!(store.contains(*hash));
+    }
+
+    assert_eq!(
+      store.manifest(package.hash).unwrap().unwrap(),
+      package.manifest
+    );
+
+    assert!(store.unpin(package.hash).unwrap());
+    assert!(!store.unpin(package.hash).unwrap());
+
+    assert_eq!(store.gc().unwrap().blobs, package.files.len().into_u64());
+
+    assert_eq!(fs::read_dir(tempdir.join("data/blobs")).unwrap().count(), 0);
+  }
+}
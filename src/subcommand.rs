@@ -18,6 +18,7 @@ mod server;
     .literal(AnsiColor::Blue.on_default() | Effects::BOLD)
     .placeholder(AnsiColor::Cyan.on_default()))
 ]
+#[allow(clippy::large_enum_variant)]
 pub(crate) enum Subcommand {
   Package(package::Package),
   Server(server::Server),
@@ -1,4 +1,12 @@
-use super::*;
+use {
+  super::*,
+  indicatif::{ProgressBar, ProgressStyle},
+  optimize::Optimize,
+  thumbnails::Thumbnails,
+};
+
+pub(crate) mod optimize;
+mod thumbnails;
 
 #[derive(Parser)]
 pub(crate) struct Package {
@@ -6,9 +14,18 @@ pub(crate) struct Package {
   pub(crate) root: Utf8PathBuf,
   #[arg(long, help = "Save package to <OUTPUT>.")]
   pub(crate) output: Utf8PathBuf,
+  #[arg(long, help = "Compress files with zstd when it makes them smaller.")]
+  pub(crate) compress: bool,
+  #[command(flatten)]
+  pub(crate) optimize: Optimize,
+  #[arg(long, help = "Generate cover and page thumbnails.")]
+  pub(crate) thumbnails: bool,
 }
 
 impl Package {
+  const PROGRESS_TEMPLATE: &'static str =
+    "{bytes}/{total_bytes} [{wide_bar}] {binary_bytes_per_sec} {eta}";
+
   pub(crate) fn run(self) -> Result {
     ensure!(
       !self.output.starts_with(&self.root),
@@ -36,36 +53,90 @@ impl Package {
 
     let paths = self.paths()?;
 
-    let template = metadata.template(&self.root, &paths)?;
+    let mut template = metadata.template(&self.root, &paths)?;
 
-    let hashes = self.hashes(paths)?;
+    let staging = if self.optimize.is_enabled() {
+      Some(tempfile::tempdir().context(error::Staging)?)
+    } else {
+      None
+    };
 
-    let manifest = template.manifest(&hashes);
+    let (root, paths, savings) = match &staging {
+      Some(staging) => {
+        let staging = staging.path().try_into_utf8()?;
+        let (savings, renamed) = self.optimize.run(&self.root, &paths, staging)?;
+        template.rename(&renamed);
+        (staging, renamed.into_values().collect(), Some(savings))
+      }
+      None => (self.root.as_path(), paths, None),
+    };
 
-    super::Package::save(hashes, &manifest, &self.output, &self.root)
+    let mut writer = crate::package::Writer::new(self.compress)
       .context(error::PackageSave { path: &self.output })?;
 
+    let hashes = Self::write(root, paths, &mut writer)?;
+
+    let thumbnails = if self.thumbnails {
+      Some(Thumbnails::generate(root, template.pages())?)
+    } else {
+      None
+    };
+
+    if let Some(thumbnails) = &thumbnails {
+      for content in thumbnails.contents() {
+        writer
+          .add(content)
+          .context(error::PackageSave { path: &self.output })?;
+      }
+    }
+
+    let manifest = template.manifest(&hashes, thumbnails.as_ref().map(Thumbnails::manifest));
+
+    writer
+      .finish(&manifest, &self.output)
+      .context(error::PackageSave { path: &self.output })?;
+
+    if let Some(savings) = savings {
+      eprintln!("{savings}");
+    }
+
     Ok(())
   }
 
-  fn hashes(&self, paths: HashSet<Utf8PathBuf>) -> Result<HashMap<Utf8PathBuf, (Hash, u64)>> {
+  fn write(
+    root: &Utf8Path,
+    paths: HashSet<Utf8PathBuf>,
+    writer: &mut crate::package::Writer,
+  ) -> Result<HashMap<Utf8PathBuf, (Hash, u64)>> {
+    let mut total = 0;
+
+    for relative in &paths {
+      let path = root.join(relative);
+      total += path.metadata().context(error::Io { path: &path })?.len();
+    }
+
+    let progress = ProgressBar::new(total).with_style(
+      ProgressStyle::with_template(Self::PROGRESS_TEMPLATE)
+        .unwrap()
+        .progress_chars("=> "),
+    );
+
     let mut hashes = HashMap::new();
 
     for relative in paths {
-      let path = self.root.join(&relative);
+      let path = root.join(&relative);
 
-      let context = error::Io { path: &path };
+      let file = File::open(&path).context(error::Io { path: &path })?;
 
-      let file = File::open(&path).context(context)?;
+      let hash = writer
+        .add(progress.wrap_read(file))
+        .context(error::PackageAdd { path: &path })?;
 
-      let len = file.metadata().context(context)?.len();
-
-      hashes.insert(
-        relative.clone(),
-        (Hash::reader(file).context(context)?, len),
-      );
+      hashes.insert(relative, hash);
     }
 
+    progress.finish();
+
     Ok(hashes)
   }
 
@@ -106,6 +177,9 @@ mod tests {
     let tempdir = tempdir();
 
     let result = Package {
+      compress: false,
+      optimize: Optimize::default(),
+      thumbnails: false,
       root: "tests/packages/comic".into(),
       output: tempdir.join("output.package"),
     }
@@ -121,6 +195,9 @@ mod tests {
   fn output_in_root_error() {
     assert_matches!(
       Package {
+        compress: false,
+        optimize: Optimize::default(),
+        thumbnails: false,
         root: "foo".into(),
         output: "foo/bar".into(),
       }
@@ -145,6 +222,9 @@ mod tests {
 
     assert_matches!(
       Package {
+        compress: false,
+        optimize: Optimize::default(),
+        thumbnails: false,
         root: "foo".into(),
         output: output_dir.clone(),
       }
@@ -169,6 +249,9 @@ mod tests {
 
     assert_matches!(
       Package {
+        compress: false,
+        optimize: Optimize::default(),
+        thumbnails: false,
         root: root_dir.clone(),
         output,
       }
@@ -208,7 +291,9 @@ mod tests {
       "root/metadata.yaml",
       Metadata {
         name: "comic".into(),
-        media: metadata::Media::Comic,
+        media: metadata::Media::Comic {
+          pages: metadata::Pages::default(),
+        },
       },
     );
 
@@ -216,6 +301,9 @@ mod tests {
     tempdir.write("root/1.jpg", "bar");
 
     Package {
+      compress: false,
+      optimize: Optimize::default(),
+      thumbnails: false,
       root,
       output: output.clone(),
     }
@@ -239,9 +327,9 @@ mod tests {
     assert_eq!(pages[0], foo);
     assert_eq!(pages[1], bar);
 
-    assert_eq!(package.files[&foo], "foo".as_bytes());
-    assert_eq!(package.files[&bar], "bar".as_bytes());
-    assert_eq!(package.files[&manifest], manifest_bytes);
+    assert_eq!(package.files[&foo].data, "foo".as_bytes());
+    assert_eq!(package.files[&bar].data, "bar".as_bytes());
+    assert_eq!(package.files[&manifest].data, manifest_bytes);
   }
 
   #[test]
@@ -255,7 +343,9 @@ mod tests {
       "root/metadata.yaml",
       Metadata {
         name: "comic".into(),
-        media: metadata::Media::Comic,
+        media: metadata::Media::Comic {
+          pages: metadata::Pages::default(),
+        },
       },
     );
 
@@ -263,7 +353,15 @@ mod tests {
 
     fs::create_dir(root.join("bar")).unwrap();
 
-    Package { root, output }.run().unwrap();
+    Package {
+      compress: false,
+      optimize: Optimize::default(),
+      thumbnails: false,
+      root,
+      output,
+    }
+    .run()
+    .unwrap();
   }
 
   #[test]
@@ -277,14 +375,24 @@ mod tests {
       "root/metadata.yaml",
       Metadata {
         name: "comic".into(),
-        media: metadata::Media::Comic,
+        media: metadata::Media::Comic {
+          pages: metadata::Pages::default(),
+        },
       },
     );
 
     tempdir.touch("root/0.jpg");
     tempdir.touch("root/.DS_Store");
 
-    Package { root, output }.run().unwrap();
+    Package {
+      compress: false,
+      optimize: Optimize::default(),
+      thumbnails: false,
+      root,
+      output,
+    }
+    .run()
+    .unwrap();
   }
 
   #[test]
@@ -298,12 +406,17 @@ mod tests {
       "root/metadata.yaml",
       Metadata {
         name: "comic".into(),
-        media: metadata::Media::Comic,
+        media: metadata::Media::Comic {
+          pages: metadata::Pages::default(),
+        },
       },
     );
 
     assert_matches!(
       Package {
+        compress: false,
+        optimize: Optimize::default(),
+        thumbnails: false,
         root: root_dir.clone(),
         output,
       }
@@ -328,7 +441,9 @@ mod tests {
       "root/metadata.yaml",
       Metadata {
         name: "comic".into(),
-        media: metadata::Media::Comic,
+        media: metadata::Media::Comic {
+          pages: metadata::Pages::default(),
+        },
       },
     );
 
@@ -336,6 +451,9 @@ mod tests {
 
     assert_matches!(
       Package {
+        compress: false,
+        optimize: Optimize::default(),
+        thumbnails: false,
         root,
         output,
       }
@@ -360,7 +478,9 @@ mod tests {
       "root/metadata.yaml",
       Metadata {
         name: "comic".into(),
-        media: metadata::Media::Comic,
+        media: metadata::Media::Comic {
+          pages: metadata::Pages::default(),
+        },
       },
     );
 
@@ -369,6 +489,9 @@ mod tests {
 
     assert_matches!(
       Package {
+        compress: false,
+        optimize: Optimize::default(),
+        thumbnails: false,
         root,
         output,
       }
@@ -393,7 +516,9 @@ mod tests {
       "root/metadata.yaml",
       Metadata {
         name: "comic".into(),
-        media: metadata::Media::Comic,
+        media: metadata::Media::Comic {
+          pages: metadata::Pages::default(),
+        },
       },
     );
 
@@ -402,6 +527,9 @@ mod tests {
 
     assert_matches!(
       Package {
+        compress: false,
+        optimize: Optimize::default(),
+        thumbnails: false,
         root,
         output,
       }
@@ -427,7 +555,9 @@ mod tests {
       "root/metadata.yaml",
       Metadata {
         name: "comic".into(),
-        media: metadata::Media::Comic,
+        media: metadata::Media::Comic {
+          pages: metadata::Pages::default(),
+        },
       },
     );
 
@@ -435,6 +565,9 @@ mod tests {
 
     assert_matches!(
       Package {
+        compress: false,
+        optimize: Optimize::default(),
+        thumbnails: false,
         root,
         output,
       }
@@ -459,7 +592,9 @@ mod tests {
       "root/metadata.yaml",
       Metadata {
         name: "Foo".into(),
-        media: metadata::Media::Comic,
+        media: metadata::Media::Comic {
+          pages: metadata::Pages::default(),
+        },
       },
     );
 
@@ -467,6 +602,9 @@ mod tests {
     tempdir.write("root/1.jpg", "foo");
 
     Package {
+      compress: false,
+      optimize: Optimize::default(),
+      thumbnails: false,
       root: root.clone(),
       output: output.clone(),
     }
@@ -477,4 +615,315 @@ mod tests {
 
     assert_eq!(package.files.len(), 2);
   }
+
+  #[test]
+  fn comic_pages_in_natural_order() {
+    let tempdir = tempdir();
+
+    let root = tempdir.join("root");
+    let output = tempdir.join("output.package");
+
+    tempdir.write_yaml(
+      "root/metadata.yaml",
+      Metadata {
+        name: "comic".into(),
+        media: metadata::Media::Comic {
+          pages: metadata::Pages::Order(metadata::Order::Natural),
+        },
+      },
+    );
+
+    tempdir.write("root/Issue 01 - p10.jpg", "baz");
+    tempdir.write("root/Issue 01 - p002.jpg", "bar");
+    tempdir.write("root/Issue 01 - p1.JPG", "foo");
+
+    Package {
+      compress: false,
+      optimize: Optimize::default(),
+      thumbnails: false,
+      root,
+      output: output.clone(),
+    }
+    .run()
+    .unwrap_or_display();
+
+    let package = super::super::Package::load(&output).unwrap_or_display();
+
+    let Media::Comic { pages } = package.manifest.media;
+
+    assert_eq!(
+      pages,
+      [
+        Hash::bytes(b"foo"),
+        Hash::bytes(b"bar"),
+        Hash::bytes(b"baz"),
+      ],
+    );
+  }
+
+  #[test]
+  fn comic_natural_order_unexpected_file() {
+    let tempdir = tempdir();
+
+    let root = tempdir.join("root");
+    let output = tempdir.join("output.package");
+
+    tempdir.write_yaml(
+      "root/metadata.yaml",
+      Metadata {
+        name: "comic".into(),
+        media: metadata::Media::Comic {
+          pages: metadata::Pages::Order(metadata::Order::Natural),
+        },
+      },
+    );
+
+    tempdir.touch("root/p1.jpg");
+    tempdir.touch("root/notes.txt");
+
+    assert_matches!(
+      Package {
+      compress: false,
+      optimize: Optimize::default(),
+      thumbnails: false,
+      root,
+      output,
+    }.run().unwrap_err(),
+      Error::UnexpectedFile { file, ty, .. }
+      if file == "notes.txt" && ty == Type::Comic,
+    );
+  }
+
+  #[test]
+  fn comic_pages_in_listed_order() {
+    let tempdir = tempdir();
+
+    let root = tempdir.join("root");
+    let output = tempdir.join("output.package");
+
+    tempdir.write_yaml(
+      "root/metadata.yaml",
+      Metadata {
+        name: "comic".into(),
+        media: metadata::Media::Comic {
+          pages: metadata::Pages::List(vec!["cover.jpg".into(), "inside/a.jpg".into()]),
+        },
+      },
+    );
+
+    tempdir.write("root/inside/a.jpg", "bar");
+    tempdir.write("root/cover.jpg", "foo");
+
+    Package {
+      compress: false,
+      optimize: Optimize::default(),
+      thumbnails: false,
+      root,
+      output: output.clone(),
+    }
+    .run()
+    .unwrap_or_display();
+
+    let package = super::super::Package::load(&output).unwrap_or_display();
+
+    let Media::Comic { pages } = package.manifest.media;
+
+    assert_eq!(pages, [Hash::bytes(b"foo"), Hash::bytes(b"bar")]);
+  }
+
+  #[test]
+  fn comic_listed_page_not_found() {
+    let tempdir = tempdir();
+
+    let root = tempdir.join("root");
+    let output = tempdir.join("output.package");
+
+    tempdir.write_yaml(
+      "root/metadata.yaml",
+      Metadata {
+        name: "comic".into(),
+        media: metadata::Media::Comic {
+          pages: metadata::Pages::List(vec!["a.jpg".into(), "b.jpg".into()]),
+        },
+      },
+    );
+
+    tempdir.touch("root/a.jpg");
+
+    assert_matches!(
+      Package {
+      compress: false,
+      optimize: Optimize::default(),
+      thumbnails: false,
+      root,
+      output,
+    }.run().unwrap_err(),
+      Error::PageNotFound { path, .. }
+      if path == "b.jpg",
+    );
+  }
+
+  #[test]
+  fn comic_listed_page_repeated() {
+    let tempdir = tempdir();
+
+    let root = tempdir.join("root");
+    let output = tempdir.join("output.package");
+
+    tempdir.write_yaml(
+      "root/metadata.yaml",
+      Metadata {
+        name: "comic".into(),
+        media: metadata::Media::Comic {
+          pages: metadata::Pages::List(vec!["a.jpg".into(), "a.jpg".into()]),
+        },
+      },
+    );
+
+    tempdir.touch("root/a.jpg");
+
+    assert_matches!(
+      Package {
+      compress: false,
+      optimize: Optimize::default(),
+      thumbnails: false,
+      root,
+      output,
+    }.run().unwrap_err(),
+      Error::PageRepeated { path, .. }
+      if path == "a.jpg",
+    );
+  }
+
+  #[test]
+  fn comic_unlisted_page() {
+    let tempdir = tempdir();
+
+    let root = tempdir.join("root");
+    let output = tempdir.join("output.package");
+
+    tempdir.write_yaml(
+      "root/metadata.yaml",
+      Metadata {
+        name: "comic".into(),
+        media: metadata::Media::Comic {
+          pages: metadata::Pages::List(vec!["a.jpg".into()]),
+        },
+      },
+    );
+
+    tempdir.touch("root/a.jpg");
+    tempdir.touch("root/b.jpg");
+
+    assert_matches!(
+      Package {
+      compress: false,
+      optimize: Optimize::default(),
+      thumbnails: false,
+      root,
+      output,
+    }.run().unwrap_err(),
+      Error::UnexpectedFile { file, ty, .. }
+      if file == "b.jpg" && ty == Type::Comic,
+    );
+  }
+
+  #[test]
+  fn optimized_pages_are_packaged() {
+    let tempdir = tempdir();
+
+    let output = tempdir.join("output.package");
+
+    Package {
+      compress: false,
+      optimize: Optimize {
+        max_dimension: Some(4),
+        format: Some(optimize::Format::Png),
+        ..Default::default()
+      },
+      thumbnails: false,
+      root: "tests/packages/comic".into(),
+      output: output.clone(),
+    }
+    .run()
+    .unwrap_or_display();
+
+    let package = super::super::Package::load(&output).unwrap_or_display();
+
+    let Media::Comic { pages } = package.manifest.media;
+
+    assert_eq!(pages.len(), 3);
+
+    for page in pages {
+      let image = image::load_from_memory(&package.files[&page].data).unwrap();
+      assert!(image.width() <= 4 && image.height() <= 4);
+    }
+  }
+
+  #[test]
+  fn optimized_pages_have_thumbnails() {
+    let tempdir = tempdir();
+
+    let output = tempdir.join("output.package");
+
+    Package {
+      compress: false,
+      optimize: Optimize {
+        format: Some(optimize::Format::Webp),
+        ..Default::default()
+      },
+      root: "tests/packages/comic".into(),
+      output: output.clone(),
+      thumbnails: true,
+    }
+    .run()
+    .unwrap_or_display();
+
+    let package = super::super::Package::load(&output).unwrap_or_display();
+
+    let thumbnails = package.manifest.thumbnails.clone().unwrap();
+
+    assert_eq!(thumbnails.pages.len(), 3);
+
+    assert_eq!(
+      image::guess_format(&package.files[&thumbnails.cover].data).unwrap(),
+      image::ImageFormat::Jpeg,
+    );
+  }
+
+  #[test]
+  fn thumbnails_are_packaged() {
+    let tempdir = tempdir();
+
+    let output = tempdir.join("output.package");
+
+    Package {
+      compress: false,
+      optimize: Optimize::default(),
+      root: "tests/packages/comic".into(),
+      output: output.clone(),
+      thumbnails: true,
+    }
+    .run()
+    .unwrap_or_display();
+
+    let package = super::super::Package::load(&output).unwrap_or_display();
+
+    let thumbnails = package.manifest.thumbnails.clone().unwrap();
+
+    assert_eq!(thumbnails.pages.len(), 3);
+
+    assert_eq!(
+      package.file("cover.jpg").unwrap().1,
+      package.files[&thumbnails.cover].data,
+    );
+
+    assert_eq!(
+      package.file("2.thumbnail.jpg").unwrap().1,
+      package.files[&thumbnails.pages[2]].data,
+    );
+
+    assert!(package.file("3.thumbnail.jpg").is_none());
+    assert!(package.file("02.thumbnail.jpg").is_none());
+  }
 }
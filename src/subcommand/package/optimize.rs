@@ -0,0 +1,496 @@
+use {
+  super::*,
+  clap::{value_parser, Args, ValueEnum},
+  image::{
+    codecs::{avif::AvifEncoder, jpeg::JpegEncoder, webp::WebPEncoder},
+    imageops::FilterType,
+    DynamicImage, ImageFormat,
+  },
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub(crate) enum Format {
+  Avif,
+  Jpeg,
+  Png,
+  Webp,
+}
+
+impl Display for Format {
+  fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+    f.write_str(self.to_possible_value().unwrap().get_name())
+  }
+}
+
+impl Format {
+  fn extension(self) -> &'static str {
+    match self {
+      Self::Avif => "avif",
+      Self::Jpeg => "jpg",
+      Self::Png => "png",
+      Self::Webp => "webp",
+    }
+  }
+
+  fn is_lossless(self) -> bool {
+    matches!(self, Self::Png | Self::Webp)
+  }
+
+  fn image_format(self) -> ImageFormat {
+    match self {
+      Self::Avif => ImageFormat::Avif,
+      Self::Jpeg => ImageFormat::Jpeg,
+      Self::Png => ImageFormat::Png,
+      Self::Webp => ImageFormat::WebP,
+    }
+  }
+}
+
+#[derive(Args, Clone, Debug, Default)]
+pub(crate) struct Optimize {
+  #[arg(long, help = "Re-encode images as <FORMAT>.")]
+  pub(crate) format: Option<Format>,
+  #[arg(
+    long,
+    help = "Resize images so that neither dimension exceeds <MAX_DIMENSION> pixels."
+  )]
+  pub(crate) max_dimension: Option<u32>,
+  #[arg(
+    long,
+    help = "Encode JPEG and AVIF images with <QUALITY>, from 1 to 100. PNG and WebP images are \
+            always encoded losslessly.",
+    value_parser = value_parser!(u8).range(1..=100),
+  )]
+  pub(crate) quality: Option<u8>,
+  #[arg(long, help = "Strip EXIF metadata from JPEG images.")]
+  pub(crate) strip_exif: bool,
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct Savings {
+  pub(crate) after: u64,
+  pub(crate) before: u64,
+  pub(crate) images: u64,
+}
+
+impl Display for Savings {
+  fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+    let saved = self.before.saturating_sub(self.after);
+
+    write!(
+      f,
+      "optimized {} images from {} to {} bytes, saving {saved} bytes",
+      self.images, self.before, self.after,
+    )?;
+
+    if self.before > 0 {
+      write!(f, " ({:.1}%)", saved as f64 / self.before as f64 * 100.0)?;
+    }
+
+    Ok(())
+  }
+}
+
+impl Optimize {
+  const AVIF_SPEED: u8 = 4;
+  const DEFAULT_QUALITY: u8 = 90;
+
+  pub(crate) fn is_enabled(&self) -> bool {
+    self.format.is_some()
+      || self.max_dimension.is_some()
+      || self.quality.is_some()
+      || self.strip_exif
+  }
+
+  // returns the path each image was saved to in `staging`, which has the
+  // extension of the new format if the image was re-encoded
+  pub(crate) fn run(
+    &self,
+    root: &Utf8Path,
+    paths: &HashSet<Utf8PathBuf>,
+    staging: &Utf8Path,
+  ) -> Result<(Savings, HashMap<Utf8PathBuf, Utf8PathBuf>)> {
+    if let (Some(format), Some(_)) = (self.format, self.quality) {
+      ensure!(!format.is_lossless(), error::QualityUnsupported { format });
+    }
+
+    let mut savings = Savings::default();
+
+    let mut renamed = HashMap::new();
+
+    let mut sources = HashMap::<Utf8PathBuf, &Utf8PathBuf>::new();
+
+    let mut paths = paths.iter().collect::<Vec<&Utf8PathBuf>>();
+
+    paths.sort();
+
+    for relative in paths {
+      let path = root.join(relative);
+
+      let input = fs::read(&path).context(error::Io { path: &path })?;
+
+      let output = self.image(&input, &path)?;
+
+      // images converted to another format get its extension
+      let staged = match self.format {
+        Some(format) if image::guess_format(&input).ok() != Some(format.image_format()) => {
+          relative.with_extension(format.extension())
+        }
+        _ => relative.clone(),
+      };
+
+      if let Some(a) = sources.insert(staged.clone(), relative) {
+        return error::OptimizedPathConflict {
+          a,
+          b: relative,
+          path: staged,
+        }
+        .fail();
+      }
+
+      log::debug!(
+        "optimized `{relative}` from {} to {} bytes",
+        input.len(),
+        output.len(),
+      );
+
+      if output.len() > input.len() {
+        eprintln!(
+          "warning: optimizing `{relative}` increased its size from {} to {} bytes",
+          input.len(),
+          output.len(),
+        );
+      }
+
+      savings.images += 1;
+      savings.before += input.len().into_u64();
+      savings.after += output.len().into_u64();
+
+      let destination = staging.join(&staged);
+
+      if let Some(parent) = destination.parent() {
+        fs::create_dir_all(parent).context(error::Io { path: parent })?;
+      }
+
+      fs::write(&destination, output).context(error::Io { path: &destination })?;
+
+      renamed.insert(relative.clone(), staged);
+    }
+
+    Ok((savings, renamed))
+  }
+
+  fn image(&self, input: &[u8], path: &Utf8Path) -> Result<Vec<u8>> {
+    let format = image::guess_format(input).context(error::Image { path })?;
+
+    let target = self.format.map(Format::image_format).unwrap_or(format);
+
+    // quality only applies to lossy formats
+    let requality =
+      self.quality.is_some() && matches!(target, ImageFormat::Avif | ImageFormat::Jpeg);
+
+    let mut decoded = None;
+
+    if let Some(max) = self.max_dimension {
+      let image =
+        image::load_from_memory_with_format(input, format).context(error::Image { path })?;
+
+      if image.width() > max || image.height() > max {
+        decoded = Some(image.resize(max, max, FilterType::Lanczos3));
+      }
+    }
+
+    let output = if decoded.is_some() || target != format || requality {
+      let image = match decoded {
+        Some(image) => image,
+        None => {
+          image::load_from_memory_with_format(input, format).context(error::Image { path })?
+        }
+      };
+
+      self.encode(&image, target).context(error::Image { path })?
+    } else {
+      input.to_vec()
+    };
+
+    if self.strip_exif && target == ImageFormat::Jpeg {
+      strip_exif(&output).context(error::InvalidJpeg { path })
+    } else {
+      Ok(output)
+    }
+  }
+
+  fn encode(&self, image: &DynamicImage, format: ImageFormat) -> image::ImageResult<Vec<u8>> {
+    let quality = self.quality.unwrap_or(Self::DEFAULT_QUALITY);
+
+    let mut output = Vec::new();
+
+    match format {
+      ImageFormat::Avif => DynamicImage::from(image.to_rgba8()).write_with_encoder(
+        AvifEncoder::new_with_speed_quality(&mut output, Self::AVIF_SPEED, quality),
+      )?,
+      ImageFormat::Jpeg => DynamicImage::from(image.to_rgb8())
+        .write_with_encoder(JpegEncoder::new_with_quality(&mut output, quality))?,
+      ImageFormat::WebP => DynamicImage::from(image.to_rgba8())
+        .write_with_encoder(WebPEncoder::new_lossless(&mut output))?,
+      format => image.write_to(&mut Cursor::new(&mut output), format)?,
+    }
+
+    Ok(output)
+  }
+}
+
+fn strip_exif(jpeg: &[u8]) -> Option<Vec<u8>> {
+  const APP1: u8 = 0xE1;
+  const EOI: u8 = 0xD9;
+  const SOI: [u8; 2] = [0xFF, 0xD8];
+  const SOS: u8 = 0xDA;
+
+  let segments = jpeg.strip_prefix(&SOI)?;
+
+  let mut output = Vec::with_capacity(jpeg.len());
+
+  output.extend_from_slice(&SOI);
+
+  let mut i = 0;
+
+  loop {
+    if *segments.get(i)? != 0xFF {
+      return None;
+    }
+
+    let marker = *segments.get(i + 1)?;
+
+    if marker == 0xFF {
+      i += 1;
+      continue;
+    }
+
+    if marker == EOI {
+      output.extend_from_slice(&segments[i..]);
+      return Some(output);
+    }
+
+    let len = usize::from(u16::from_be_bytes([
+      *segments.get(i + 2)?,
+      *segments.get(i + 3)?,
+    ]));
+
+    if len < 2 {
+      return None;
+    }
+
+    let end = i + 2 + len;
+
+    let segment = segments.get(i..end)?;
+
+    if marker != APP1 {
+      output.extend_from_slice(segment);
+    }
+
+    if marker == SOS {
+      output.extend_from_slice(&segments[end..]);
+      return Some(output);
+    }
+
+    i = end;
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use {super::*, image::RgbImage};
+
+  fn png(width: u32, height: u32) -> Vec<u8> {
+    let mut png = Vec::new();
+
+    RgbImage::new(width, height)
+      .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+      .unwrap();
+
+    png
+  }
+
+  #[test]
+  fn images_are_resized() {
+    let optimize = Optimize {
+      max_dimension: Some(10),
+      ..Default::default()
+    };
+
+    let output = optimize.image(&png(100, 50), "foo.png".into()).unwrap();
+
+    assert_eq!(image::guess_format(&output).unwrap(), ImageFormat::Png);
+
+    let image = image::load_from_memory(&output).unwrap();
+
+    assert_eq!((image.width(), image.height()), (10, 5));
+  }
+
+  #[test]
+  fn small_images_are_not_resized() {
+    let optimize = Optimize {
+      max_dimension: Some(100),
+      ..Default::default()
+    };
+
+    let input = png(10, 5);
+
+    assert_eq!(optimize.image(&input, "foo.png".into()).unwrap(), input);
+  }
+
+  #[test]
+  fn images_are_reencoded() {
+    #[track_caller]
+    fn case(format: Format, expected: ImageFormat) {
+      let optimize = Optimize {
+        format: Some(format),
+        quality: Some(50),
+        ..Default::default()
+      };
+
+      let output = optimize.image(&png(8, 8), "foo.png".into()).unwrap();
+
+      assert_eq!(image::guess_format(&output).unwrap(), expected);
+    }
+
+    case(Format::Avif, ImageFormat::Avif);
+    case(Format::Jpeg, ImageFormat::Jpeg);
+    case(Format::Webp, ImageFormat::WebP);
+  }
+
+  #[test]
+  fn reencoded_images_are_renamed() {
+    let root = tempdir();
+    root.write("0.png", png(8, 8));
+    root.write("1.png", png(8, 8));
+
+    let staging = tempdir();
+
+    let optimize = Optimize {
+      format: Some(Format::Webp),
+      ..Default::default()
+    };
+
+    let paths = ["0.png".into(), "1.png".into()].into();
+
+    let (_savings, renamed) = optimize
+      .run(root.path_utf8(), &paths, staging.path_utf8())
+      .unwrap();
+
+    assert_eq!(
+      renamed,
+      [
+        ("0.png".into(), "0.webp".into()),
+        ("1.png".into(), "1.webp".into()),
+      ]
+      .into(),
+    );
+
+    assert_eq!(
+      image::guess_format(&fs::read(staging.join("0.webp")).unwrap()).unwrap(),
+      ImageFormat::WebP,
+    );
+
+    root.write("0.jpg", png(8, 8));
+
+    assert_matches!(
+      optimize.run(
+        root.path_utf8(),
+        &["0.jpg".into(), "0.png".into()].into(),
+        staging.path_utf8(),
+      ),
+      Err(Error::OptimizedPathConflict { a, b, path, .. })
+      if a == "0.jpg" && b == "0.png" && path == "0.webp",
+    );
+  }
+
+  #[test]
+  fn quality_alone_reencodes_lossy_images() {
+    let mut jpeg = Vec::new();
+
+    DynamicImage::from(RgbImage::from_fn(64, 64, |x, y| {
+      image::Rgb([(x * 4) as u8, (y * 4) as u8, (x ^ y) as u8])
+    }))
+    .write_with_encoder(JpegEncoder::new_with_quality(&mut jpeg, 100))
+    .unwrap();
+
+    let optimize = Optimize {
+      quality: Some(10),
+      ..Default::default()
+    };
+
+    assert!(optimize.is_enabled());
+
+    let output = optimize.image(&jpeg, "foo.jpg".into()).unwrap();
+
+    assert_eq!(image::guess_format(&output).unwrap(), ImageFormat::Jpeg);
+
+    assert!(output.len() < jpeg.len());
+
+    let input = png(8, 8);
+
+    assert_eq!(optimize.image(&input, "foo.png".into()).unwrap(), input);
+  }
+
+  #[test]
+  fn quality_is_rejected_for_lossless_formats() {
+    for format in [Format::Png, Format::Webp] {
+      let optimize = Optimize {
+        format: Some(format),
+        quality: Some(50),
+        ..Default::default()
+      };
+
+      assert_matches!(
+        optimize.run("root".into(), &HashSet::new(), "staging".into()),
+        Err(Error::QualityUnsupported { format: actual, .. }) if actual == format,
+      );
+    }
+
+    assert_eq!(
+      Error::QualityUnsupported {
+        backtrace: None,
+        format: Format::Webp,
+      }
+      .to_string(),
+      "--quality may not be used with lossless format webp",
+    );
+  }
+
+  #[test]
+  fn exif_is_stripped() {
+    let mut jpeg = Vec::new();
+    jpeg.extend_from_slice(&[0xFF, 0xD8]);
+    jpeg.extend_from_slice(&[0xFF, 0xE0, 0x00, 0x04, 0xAA, 0xAA]);
+    jpeg.extend_from_slice(&[0xFF, 0xE1, 0x00, 0x06, b'E', b'x', b'i', b'f']);
+    jpeg.extend_from_slice(&[0xFF, 0xDA, 0x00, 0x02, 0x01, 0x02, 0xFF, 0xD9]);
+
+    let mut expected = Vec::new();
+    expected.extend_from_slice(&[0xFF, 0xD8]);
+    expected.extend_from_slice(&[0xFF, 0xE0, 0x00, 0x04, 0xAA, 0xAA]);
+    expected.extend_from_slice(&[0xFF, 0xDA, 0x00, 0x02, 0x01, 0x02, 0xFF, 0xD9]);
+
+    assert_eq!(strip_exif(&jpeg).unwrap(), expected);
+  }
+
+  #[test]
+  fn invalid_jpeg_is_rejected() {
+    assert_eq!(strip_exif(b"foo"), None);
+    assert_eq!(strip_exif(&[0xFF, 0xD8, 0xFF, 0xE1, 0x00, 0x10]), None);
+    assert_eq!(strip_exif(&[0xFF, 0xD8, 0xFF, 0xE1, 0x00, 0x00]), None);
+  }
+
+  #[test]
+  fn savings_display() {
+    assert_eq!(
+      Savings {
+        after: 25,
+        before: 100,
+        images: 2,
+      }
+      .to_string(),
+      "optimized 2 images from 100 to 25 bytes, saving 75 bytes (75.0%)",
+    );
+  }
+}
@@ -0,0 +1,70 @@
+use {
+  super::*,
+  image::{codecs::jpeg::JpegEncoder, DynamicImage, ImageReader},
+};
+
+pub(crate) struct Thumbnails {
+  cover: Vec<u8>,
+  pages: Vec<Vec<u8>>,
+}
+
+impl Thumbnails {
+  const COVER_DIMENSION: u32 = 512;
+  const PAGE_DIMENSION: u32 = 256;
+  const QUALITY: u8 = 80;
+
+  pub(crate) fn generate(root: &Utf8Path, pages: &[Utf8PathBuf]) -> Result<Self> {
+    let mut cover = None;
+    let mut thumbnails = Vec::new();
+
+    for page in pages {
+      let path = root.join(page);
+
+      // optimized pages keep their original names, so the extension may not
+      // match the format
+      let image = ImageReader::open(&path)
+        .and_then(ImageReader::with_guessed_format)
+        .context(error::Io { path: &path })?
+        .decode()
+        .context(error::Image { path: &path })?;
+
+      if cover.is_none() {
+        cover = Some(Self::encode(&image, Self::COVER_DIMENSION, &path)?);
+      }
+
+      thumbnails.push(Self::encode(&image, Self::PAGE_DIMENSION, &path)?);
+    }
+
+    Ok(Self {
+      cover: cover.context(error::NoPages { root })?,
+      pages: thumbnails,
+    })
+  }
+
+  fn encode(image: &DynamicImage, dimension: u32, path: &Utf8Path) -> Result<Vec<u8>> {
+    let mut thumbnail = Vec::new();
+
+    DynamicImage::from(image.thumbnail(dimension, dimension).to_rgb8())
+      .write_with_encoder(JpegEncoder::new_with_quality(&mut thumbnail, Self::QUALITY))
+      .context(error::Image { path })?;
+
+    Ok(thumbnail)
+  }
+
+  pub(crate) fn contents(&self) -> impl Iterator<Item = &[u8]> {
+    iter::once(&self.cover)
+      .chain(&self.pages)
+      .map(Vec::as_slice)
+  }
+
+  pub(crate) fn manifest(&self) -> manifest::Thumbnails {
+    manifest::Thumbnails {
+      cover: Hash::bytes(&self.cover),
+      pages: self
+        .pages
+        .iter()
+        .map(|content| Hash::bytes(content))
+        .collect(),
+    }
+  }
+}
@@ -1,20 +1,31 @@
 use {
   self::{
+    admin::Admin,
+    api::{PeerResults, SearchResults},
+    catalog::Catalog,
     server_error::ServerError,
-    templates::{NodeHtml, PackageHtml, PageHtml, SearchHtml},
+    templates::{LibraryHtml, NodeHtml, PackageHtml, PageHtml, QueryHtml, SearchHtml},
   },
   super::*,
   axum::{
-    extract::{Extension, Path},
-    http::StatusCode,
+    body::Bytes,
+    extract::{ConnectInfo, DefaultBodyLimit, Extension, Path, Query, Request},
+    handler::Handler,
+    http::{HeaderMap, HeaderValue, StatusCode},
+    middleware::{self, Next},
+    response::Redirect,
     response::{IntoResponse, Response},
-    routing::get,
-    Router,
+    routing::{get, post},
+    Form, Json, Router,
   },
+  futures_util::{Stream, StreamExt},
   rust_embed::RustEmbed,
-  tokio::runtime::Runtime,
+  tokio::{runtime::Runtime, sync::Semaphore, task::JoinSet},
 };
 
+mod admin;
+mod api;
+mod catalog;
 mod server_error;
 mod templates;
 
@@ -23,7 +34,15 @@ mod templates;
 struct StaticAssets;
 
 #[derive(Parser)]
+#[command(group(clap::ArgGroup::new("storage").args(["library", "data_dir"]).multiple(true)))]
 pub(crate) struct Server {
+  #[arg(
+    long,
+    help = "Allow adding, saving, and removing packages with bearer token <TOKEN>.",
+    requires = "storage",
+    value_name = "<TOKEN>"
+  )]
+  admin_token: Option<String>,
   #[arg(
     long,
     help = "Listen on <ADDRESS> for incoming requests.",
@@ -36,16 +55,135 @@ pub(crate) struct Server {
     default_value = "80"
   )]
   http_port: u16,
+  #[arg(
+    long,
+    help = "Load packages from <DIR> and reload them when they change. Packages may be \
+            restricted in <DIR>/visibility.yaml. Peers on allow lists are only recognized \
+            with --network-key, since peer ids are otherwise unauthenticated.",
+    value_name = "<DIR>"
+  )]
+  library: Option<Utf8PathBuf>,
   #[arg(long, help = "Load <PACKAGE> into library.", value_name = "<PACKAGE>", num_args = 0..)]
   packages: Vec<Utf8PathBuf>,
   #[arg(long, help = "Open server in browser.")]
   open: bool,
-  #[arg(long, help = "Bootstrap DHT node with <PEER>.", value_name = "<PEER>")]
-  bootstrap: Option<Peer>,
+  #[arg(
+    long,
+    help = "Bootstrap node with <PEER>. May be given more than once.",
+    value_name = "<PEER>"
+  )]
+  bootstrap: Vec<Bootstrap>,
+  #[arg(
+    long,
+    help = "Bootstrap node with peers listed one per line in <PATH>.",
+    value_name = "<PATH>"
+  )]
+  bootstrap_file: Option<Utf8PathBuf>,
+  #[arg(
+    long,
+    help = "Only communicate with peers that share the network key derived from <SECRET>. \
+            The key authenticates peers, but does not encrypt traffic.",
+    value_name = "<SECRET>"
+  )]
+  network_key: Option<NetworkKey>,
+  #[arg(long, help = "Disable multicast peer discovery.")]
+  no_discovery: bool,
+  #[arg(
+    long,
+    help = "Discover peers in multicast group <ADDRESS>. May be given more than once. \
+            [default: 239.4.9.151 and ff02::4:9:151]",
+    value_name = "<ADDRESS>",
+    conflicts_with = "no_discovery"
+  )]
+  discovery_group: Vec<IpAddr>,
+  #[arg(
+    long,
+    help = "Send and receive discovery advertisements on <INTERFACE>, given by name or index.",
+    value_name = "<INTERFACE>",
+    conflicts_with = "no_discovery"
+  )]
+  discovery_interface: Option<String>,
+  #[arg(
+    long,
+    help = "Send and receive discovery advertisements on UDP <PORT>.",
+    value_name = "<PORT>",
+    default_value_t = discovery::DEFAULT_PORT,
+    conflicts_with = "no_discovery"
+  )]
+  discovery_port: u16,
+  #[arg(
+    long,
+    help = "Limit file downloads from peers to <BYTES> per second.",
+    value_name = "<BYTES>",
+    value_parser = clap::value_parser!(u64).range(1..)
+  )]
+  download_limit: Option<u64>,
+  #[arg(
+    long,
+    help = "Limit file uploads to peers and HTTP clients to <BYTES> per second.",
+    value_name = "<BYTES>",
+    value_parser = clap::value_parser!(u64).range(1..)
+  )]
+  upload_limit: Option<u64>,
+  #[arg(
+    long,
+    help = "Accept at most <CONNECTIONS> concurrent incoming peer connections.",
+    value_name = "<CONNECTIONS>",
+    value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..),
+    default_value_t = limits::DEFAULT_MAX_CONNECTIONS
+  )]
+  max_connections: usize,
+  #[arg(
+    long,
+    help = "Allow at most <STREAMS> concurrent streams on each peer connection.",
+    value_name = "<STREAMS>",
+    value_parser = clap::value_parser!(u32).range(1..),
+    default_value_t = limits::DEFAULT_MAX_STREAMS
+  )]
+  max_streams: u32,
+  #[arg(
+    long,
+    help = "Relay streams between peers that cannot connect to each other directly."
+  )]
+  relay: bool,
+  #[arg(
+    long,
+    help = "Limit relayed traffic to <BYTES> per second.",
+    value_name = "<BYTES>",
+    value_parser = clap::value_parser!(u64).range(1..),
+    requires = "relay"
+  )]
+  relay_bandwidth: Option<u64>,
+  #[arg(
+    long,
+    help = "Relay at most <STREAMS> streams at once.",
+    value_name = "<STREAMS>",
+    default_value_t = relay::DEFAULT_MAX_STREAMS,
+    requires = "relay"
+  )]
+  relay_max_streams: usize,
+  #[arg(
+    long,
+    help = "Store node data, such as cached manifests and content blobs, in <DIR>.",
+    value_name = "<DIR>"
+  )]
+  data_dir: Option<Utf8PathBuf>,
+}
+
+#[derive(Deserialize)]
+struct SaveForm {
+  token: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct SearchQuery {
+  #[serde(default)]
+  q: String,
 }
 
 #[derive(Debug)]
 struct Resource {
+  content_encoding: Option<&'static str>,
   content_type: Mime,
   content: Vec<u8>,
 }
@@ -53,6 +191,15 @@ struct Resource {
 impl Resource {
   fn new(content_type: Mime, content: Vec<u8>) -> Self {
     Self {
+      content_encoding: None,
+      content_type,
+      content,
+    }
+  }
+
+  fn zstd(content_type: Mime, content: Vec<u8>) -> Self {
+    Self {
+      content_encoding: Some("zstd"),
       content_type,
       content,
     }
@@ -61,23 +208,63 @@ impl Resource {
 
 impl IntoResponse for Resource {
   fn into_response(self) -> Response<Body> {
-    (
-      [(header::CONTENT_TYPE, self.content_type.to_string())],
+    let mut response = (
+      [
+        (header::CONTENT_TYPE, self.content_type.to_string()),
+        (header::VARY, header::ACCEPT_ENCODING.to_string()),
+      ],
       self.content,
     )
-      .into_response()
+      .into_response();
+
+    if let Some(content_encoding) = self.content_encoding {
+      response.headers_mut().insert(
+        header::CONTENT_ENCODING,
+        HeaderValue::from_static(content_encoding),
+      );
+    }
+
+    response
   }
 }
 
 type ServerResult<T = Resource> = std::result::Result<T, ServerError>;
 
 impl Server {
+  const MAX_CONCURRENT_FETCHES: usize = 16;
+  const MAX_FORM_LEN: usize = 1024;
+  const MAX_QUERY_LEN: usize = 1024;
+  const MAX_UPLOAD: usize = 1 << 30;
+  const SHUTDOWN_GRACE_PERIOD: Duration = Duration::from_secs(10);
+
   pub(crate) fn run(self) -> Result {
+    let discovery = self.discovery()?;
+
+    let limits = self.limits();
+
+    let relay = self.relay();
+
     let mut packages = BTreeMap::new();
 
     for path in &self.packages {
       let package = Package::load(path).context(error::PackageLoad { path })?;
-      packages.insert(package.hash, package);
+      packages.insert(package.hash, Arc::new(package));
+    }
+
+    let library = match self.library {
+      Some(dir) => {
+        let mut library = Library::new(dir);
+        library.scan()?;
+        let watcher = library.watch()?;
+        Some((library, watcher))
+      }
+      None => None,
+    };
+
+    let mut bootstrap = self.bootstrap.clone();
+
+    if let Some(path) = &self.bootstrap_file {
+      bootstrap.extend(Self::load_bootstrap_file(path)?);
     }
 
     if self.open {
@@ -87,44 +274,196 @@ impl Server {
 
     Runtime::new().context(error::Runtime)?.block_on(async {
       let node = Arc::new(
-        Node::new(self.address, packages, 0)
-          .await
-          .context(error::NodeInitialize)?,
+        Node::new(
+          self.address,
+          self.data_dir.as_deref(),
+          limits,
+          self.network_key,
+          Catalog::merge(
+            &packages,
+            library.as_ref().map(|(library, _watcher)| library),
+          ),
+          0,
+          relay,
+        )
+        .await
+        .context(error::NodeInitialize)?,
       );
 
+      let (library, watcher) = library.unzip();
+
+      let catalog = Arc::new(Catalog::new(node.clone(), packages, library));
+
+      catalog.load().await;
+
+      if let Some((watcher, events)) = watcher {
+        tokio::spawn(Self::watch(catalog.clone(), watcher, events));
+      }
+
+      tokio::spawn({
+        let node = node.clone();
+        async move { node.bootstrap(&bootstrap).await }
+      });
+
       let clone = node.clone();
       tokio::spawn(async move {
-        if let Some(bootstrap) = self.bootstrap {
-          if let Err(err) = clone.ping(bootstrap).await {
-            eprintln!("update error: {err}");
-          }
-        }
-
-        if let Err(err) = clone.run().await {
+        if let Err(err) = clone.run(discovery).await {
           eprintln!("node error: {err}");
         }
       });
 
+      let handle = axum_server::Handle::new();
+
+      tokio::spawn(Self::shutdown_on(handle.clone(), async {
+        tokio::signal::ctrl_c().await.ok();
+      }));
+
+      #[cfg(unix)]
+      {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut terminate = signal(SignalKind::terminate()).context(error::Signal)?;
+
+        tokio::spawn(Self::shutdown_on(handle.clone(), async move {
+          terminate.recv().await;
+        }));
+      }
+
       axum_server::Server::bind((self.address, self.http_port).into())
+        .handle(handle)
         .serve(
-          Router::new()
-            .route("/", get(Self::node))
-            .route("/favicon.ico", get(Self::favicon))
-            .route("/node", get(Self::node))
-            .route("/peer/:peer", get(Self::peer))
-            .route("/static/*path", get(Self::static_asset))
-            .route("/:package", get(Self::package))
-            .route("/:package/:file", get(Self::file))
-            .layer(Extension(node))
-            .into_make_service(),
+          Self::router(
+            Admin::new(self.admin_token.as_deref()),
+            catalog,
+            node.clone(),
+          )
+          .into_make_service_with_connect_info::<SocketAddr>(),
         )
         .await
         .context(error::Serve {
           address: (self.address, self.http_port),
-        })
-    })?;
+        })?;
+
+      if let Err(err) = node.save_peers().await {
+        err.report();
+      }
+
+      Ok(())
+    })
+  }
+
+  fn router(admin: Admin, catalog: Arc<Catalog>, node: Arc<Node>) -> Router {
+    let mut root = get(Self::library);
+
+    // only accept uploads when they can be authorized, and authorize them
+    // before reading the body
+    if admin.is_enabled() {
+      root = root.post(
+        Self::upload
+          .layer(middleware::from_fn(Self::authorize))
+          .layer(DefaultBodyLimit::max(Self::MAX_UPLOAD)),
+      );
+    }
+
+    Router::new()
+      .route("/", root)
+      .nest("/api/v1", api::router())
+      .route("/favicon.ico", get(Self::favicon))
+      .route("/node", get(Self::node))
+      .route("/peer/:peer", get(Self::peer))
+      .route(
+        "/peer/:peer/:package",
+        post(Self::save).layer(DefaultBodyLimit::max(Self::MAX_FORM_LEN)),
+      )
+      .route("/search", get(Self::search_page))
+      .route("/static/*path", get(Self::static_asset))
+      .route("/:package", get(Self::package).delete(Self::delete))
+      .route(
+        "/:package/:file",
+        get(Self::file).layer(middleware::from_fn(Self::throttle)),
+      )
+      .layer(Extension(Arc::new(admin)))
+      .layer(Extension(catalog))
+      .layer(Extension(node))
+  }
+
+  fn discovery(&self) -> Result<Option<discovery::Config>> {
+    if self.no_discovery {
+      return Ok(None);
+    }
+
+    let groups = if self.discovery_group.is_empty() {
+      discovery::DEFAULT_GROUPS.into()
+    } else {
+      self.discovery_group.clone()
+    };
 
-    Ok(())
+    for &group in &groups {
+      ensure!(group.is_multicast(), error::DiscoveryGroup { group });
+    }
+
+    let interface = self
+      .discovery_interface
+      .as_deref()
+      .map(|interface| {
+        discovery::interface_index(interface).context(error::DiscoveryInterface { interface })
+      })
+      .transpose()?;
+
+    Ok(Some(discovery::Config {
+      groups,
+      interface,
+      port: self.discovery_port,
+    }))
+  }
+
+  fn limits(&self) -> Limits {
+    Limits {
+      download: self.download_limit,
+      max_connections: self.max_connections,
+      max_streams: self.max_streams,
+      upload: self.upload_limit,
+    }
+  }
+
+  fn relay(&self) -> Option<relay::Config> {
+    self.relay.then_some(relay::Config {
+      bandwidth: self.relay_bandwidth,
+      max_streams: self.relay_max_streams,
+    })
+  }
+
+  fn load_bootstrap_file(path: &Utf8Path) -> Result<Vec<Bootstrap>> {
+    fs::read_to_string(path)
+      .context(error::Io { path })?
+      .lines()
+      .enumerate()
+      .map(|(i, line)| (i + 1, line.trim()))
+      .filter(|(_line, peer)| !peer.is_empty() && !peer.starts_with('#'))
+      .map(|(line, peer)| peer.parse().context(error::BootstrapPeer { line, path }))
+      .collect()
+  }
+
+  async fn shutdown_on(handle: axum_server::Handle, signal: impl Future<Output = ()>) {
+    signal.await;
+    log::info!("shutting down");
+    handle.graceful_shutdown(Some(Self::SHUTDOWN_GRACE_PERIOD));
+  }
+
+  async fn watch(
+    catalog: Arc<Catalog>,
+    _watcher: notify::RecommendedWatcher,
+    mut events: tokio::sync::mpsc::UnboundedReceiver<()>,
+  ) {
+    while events.recv().await.is_some() {
+      tokio::time::sleep(Library::DEBOUNCE).await;
+
+      while events.try_recv().is_ok() {}
+
+      if let Err(err) = catalog.reload().await {
+        log::error!("failed to reload library: {err}");
+      }
+    }
   }
 
   async fn favicon() -> ServerResult<Response> {
@@ -132,11 +471,103 @@ impl Server {
   }
 
   async fn peer(
+    admin: Extension<Arc<Admin>>,
     node: Extension<Arc<Node>>,
     peer: Path<DeserializeFromStr<Id>>,
   ) -> ServerResult<PageHtml<SearchHtml>> {
     let peer = **peer;
 
+    let (results, error) = match Self::search(&node, peer).await {
+      Ok(results) => (results, None),
+      Err(ServerError::Node { source }) if !source.is_timeout() => {
+        log::warn!("failed to search peer {peer}: {source}");
+        (PeerResults::default(), Some(source.to_string()))
+      }
+      Err(err) => return Err(err),
+    };
+
+    Ok(PageHtml {
+      packages: node.packages().await,
+      pinned: node.pinned().await,
+      main: SearchHtml {
+        admin: admin.is_enabled(),
+        error,
+        peer,
+        results,
+      },
+    })
+  }
+
+  async fn search_page(
+    node: Extension<Arc<Node>>,
+    Query(query): Query<SearchQuery>,
+  ) -> ServerResult<PageHtml<QueryHtml>> {
+    Ok(PageHtml {
+      packages: node.packages().await,
+      pinned: node.pinned().await,
+      main: QueryHtml {
+        results: Self::query(&node, &query.q).await?,
+        query: query.q,
+      },
+    })
+  }
+
+  async fn query(node: &Arc<Node>, query: &str) -> ServerResult<SearchResults> {
+    ensure!(
+      query.len() <= Self::MAX_QUERY_LEN,
+      server_error::BadRequest {
+        message: format!("query longer than {} bytes", Self::MAX_QUERY_LEN),
+      },
+    );
+
+    if query.trim().is_empty() {
+      return Ok(SearchResults::default());
+    }
+
+    let mut tasks = JoinSet::new();
+
+    for id in node.local.read().await.keys().copied() {
+      let node = node.clone();
+      let query = query.to_owned();
+      tasks.spawn(async move {
+        let hashes = match node.query(id, &query).await {
+          Ok(hashes) => hashes.unwrap_or_default(),
+          Err(err) => {
+            log::warn!("failed to query peer {id}: {err}");
+            return Err(id);
+          }
+        };
+
+        let mut unindexed = Vec::new();
+
+        for hash in hashes {
+          if !node.indexed(id, hash).await {
+            unindexed.push(hash);
+          }
+        }
+
+        Self::fetch(&node, id, unindexed).await;
+
+        Ok(())
+      });
+    }
+
+    let mut failed = BTreeSet::new();
+
+    while let Some(result) = tasks.join_next().await {
+      if let Err(id) = result.unwrap() {
+        failed.insert(id);
+      }
+    }
+
+    Ok(SearchResults {
+      failed,
+      local: node.query_local(query).await,
+      remote: node.query_remote(query).await,
+    })
+  }
+
+  async fn search(node: &Arc<Node>, peer: Id) -> ServerResult<PeerResults> {
     let hashes = node
       .search(peer)
       .await
@@ -145,25 +576,41 @@ impl Server {
         message: format!("peer {peer} not found"),
       })?;
 
-    let mut manifests = BTreeMap::new();
+    Ok(Self::fetch(node, peer, hashes).await)
+  }
+
+  async fn fetch(node: &Arc<Node>, peer: Id, hashes: Vec<Hash>) -> PeerResults {
+    let semaphore = Arc::new(Semaphore::new(Self::MAX_CONCURRENT_FETCHES));
+
+    let mut tasks = JoinSet::new();
 
     for hash in hashes {
-      let manifest = node
-        .get(peer, hash)
-        .await
-        .map_err(|source| ServerError::Node { source })?;
+      let node = node.clone();
+      let semaphore = semaphore.clone();
+      tasks.spawn(async move {
+        let _permit = semaphore.acquire().await.unwrap();
+        (hash, node.get(peer, hash).await)
+      });
+    }
 
-      let Some(manifest) = manifest else {
-        unimplemented!();
-      };
+    let mut results = PeerResults::default();
 
-      manifests.insert(hash, manifest);
+    while let Some(result) = tasks.join_next().await {
+      match result.unwrap() {
+        (hash, Ok(Some(manifest))) => {
+          results.manifests.insert(hash, manifest);
+        }
+        (hash, Ok(None)) => {
+          results.missing.insert(hash);
+        }
+        (hash, Err(err)) => {
+          log::warn!("failed to get manifest {hash} from peer {peer}: {err}");
+          results.missing.insert(hash);
+        }
+      }
     }
 
-    Ok(PageHtml {
-      packages: node.packages.clone(),
-      main: SearchHtml { peer, manifests },
-    })
+    results
   }
 
   async fn static_asset(Path(path): Path<String>) -> ServerResult<Response> {
@@ -186,12 +633,38 @@ impl Server {
     )
   }
 
+  async fn library(node: Extension<Arc<Node>>) -> PageHtml<LibraryHtml> {
+    let packages = node.packages().await;
+
+    PageHtml {
+      packages: packages.clone(),
+      pinned: node.pinned().await,
+      main: LibraryHtml {
+        packages,
+        visibility: node.visibility().await,
+      },
+    }
+  }
+
   async fn node(node: Extension<Arc<Node>>) -> PageHtml<NodeHtml> {
     PageHtml {
-      packages: node.packages.clone(),
+      packages: node.packages().await,
+      pinned: node.pinned().await,
       main: NodeHtml {
-        local: node.local.read().await.keys().copied().collect(),
+        incoming: node.incoming(),
+        limits: node.limits(),
+        local: node
+          .local
+          .read()
+          .await
+          .iter()
+          .map(|(id, contact)| (*id, *contact))
+          .collect(),
         peer: node.peer(),
+        pool: node.pool_stats(),
+        public_address: node.public_address().await,
+        relay: node.relay_stats(),
+        relayed: node.relayed().await,
         received: node.received.load(atomic::Ordering::Relaxed),
         sent: node.sent.load(atomic::Ordering::Relaxed),
       },
@@ -202,32 +675,107 @@ impl Server {
     node: Extension<Arc<Node>>,
     Path(DeserializeFromStr(package)): Path<DeserializeFromStr<Hash>>,
   ) -> ServerResult<PageHtml<PackageHtml>> {
-    let package = node
-      .packages
+    let packages = node.packages().await;
+
+    let package = packages
       .get(&package)
+      .cloned()
       .ok_or_else(|| ServerError::NotFound {
         message: format!("package {package} not found"),
       })?;
 
     Ok(PageHtml {
-      packages: node.packages.clone(),
+      packages,
+      pinned: node.pinned().await,
       main: PackageHtml {
-        package: Arc::new(package.clone()),
+        visibility: node
+          .visibility()
+          .await
+          .get(&package.hash)
+          .cloned()
+          .unwrap_or_default(),
+        package,
       },
     })
   }
 
+  async fn save(
+    admin: Extension<Arc<Admin>>,
+    catalog: Extension<Arc<Catalog>>,
+    headers: HeaderMap,
+    Path((DeserializeFromStr(peer), DeserializeFromStr(package))): Path<(
+      DeserializeFromStr<Id>,
+      DeserializeFromStr<Hash>,
+    )>,
+    form: Option<Form<SaveForm>>,
+  ) -> ServerResult<Redirect> {
+    // browsers can't send bearer tokens from a form, so also accept the
+    // token as a form field
+    admin.authorize_token(
+      Admin::bearer(&headers).or(form.as_ref().and_then(|form| form.token.as_deref())),
+    )?;
+
+    catalog.save(peer, package).await?;
+
+    Ok(Redirect::to(&format!("/{package}")))
+  }
+
+  async fn authorize(
+    Extension(admin): Extension<Arc<Admin>>,
+    headers: HeaderMap,
+    request: Request,
+    next: Next,
+  ) -> ServerResult<Response> {
+    admin.authorize(&headers)?;
+
+    Ok(next.run(request).await)
+  }
+
+  async fn upload(
+    catalog: Extension<Arc<Catalog>>,
+    body: Bytes,
+  ) -> ServerResult<(StatusCode, Json<Hash>)> {
+    let package = tokio::task::block_in_place(|| Package::from_bytes(&body))
+      .map_err(|source| ServerError::PackageInvalid { source })?;
+
+    catalog.add(&package, &body).await?;
+
+    Ok((StatusCode::CREATED, Json(package.hash)))
+  }
+
+  async fn delete(
+    admin: Extension<Arc<Admin>>,
+    catalog: Extension<Arc<Catalog>>,
+    headers: HeaderMap,
+    Path(DeserializeFromStr(package)): Path<DeserializeFromStr<Hash>>,
+  ) -> ServerResult<StatusCode> {
+    admin.authorize(&headers)?;
+
+    catalog.remove(package).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+  }
+
   async fn file(
     node: Extension<Arc<Node>>,
+    headers: HeaderMap,
     Path((DeserializeFromStr(package), file)): Path<(DeserializeFromStr<Hash>, String)>,
   ) -> ServerResult {
     let package = node
-      .packages
+      .packages()
+      .await
       .get(&package)
+      .cloned()
       .ok_or_else(|| ServerError::NotFound {
         message: format!("package {package} not found"),
       })?;
 
+    if let Some((content_type, content)) = package.content(&file) {
+      if content.compressed && Self::accepts_zstd(&headers) {
+        return Ok(Resource::zstd(content_type, content.data.clone()));
+      }
+    }
+
     match package.file(&file) {
       Some((content_type, content)) => Ok(Resource::new(content_type, content)),
       None => Err(ServerError::NotFound {
@@ -235,11 +783,362 @@ impl Server {
       }),
     }
   }
+
+  async fn throttle(
+    Extension(node): Extension<Arc<Node>>,
+    ConnectInfo(address): ConnectInfo<SocketAddr>,
+    request: Request,
+    next: Next,
+  ) -> Response {
+    let response = next.run(request).await;
+
+    if node.limits().upload.is_none() {
+      return response;
+    }
+
+    response.map(|body| Body::from_stream(Self::throttled(node, address.ip(), body)))
+  }
+
+  fn throttled(
+    node: Arc<Node>,
+    source: IpAddr,
+    body: Body,
+  ) -> impl Stream<Item = Result<Bytes, axum::Error>> {
+    futures_util::stream::unfold(
+      (body.into_data_stream(), Bytes::new()),
+      move |(mut stream, mut pending)| {
+        let node = node.clone();
+        async move {
+          if pending.is_empty() {
+            pending = match stream.next().await? {
+              Ok(bytes) => bytes,
+              Err(err) => return Some((Err(err), (stream, pending))),
+            };
+          }
+
+          let chunk = pending.split_to(pending.len().min(throttle::CHUNK_LEN));
+
+          node.upload().acquire(source, chunk.len()).await;
+
+          Some((Ok(chunk), (stream, pending)))
+        }
+      },
+    )
+  }
+
+  fn accepts_zstd(headers: &HeaderMap) -> bool {
+    headers
+      .get_all(header::ACCEPT_ENCODING)
+      .iter()
+      .filter_map(|value| value.to_str().ok())
+      .flat_map(|value| value.split(','))
+      .any(|coding| {
+        let mut parameters = coding.split(';').map(str::trim);
+
+        parameters
+          .next()
+          .is_some_and(|coding| coding.eq_ignore_ascii_case("zstd"))
+          && parameters.all(|parameter| {
+            parameter
+              .strip_prefix("q=")
+              .and_then(|q| q.parse::<f32>().ok())
+              .is_none_or(|q| q > 0.0)
+          })
+      })
+  }
 }
 
 #[cfg(test)]
 mod tests {
-  use {super::*, std::net::Ipv4Addr};
+  use {super::*, std::net::Ipv4Addr, tower::ServiceExt};
+
+  #[test]
+  fn accepts_zstd() {
+    #[track_caller]
+    fn case(accept_encoding: &[&'static str], expected: bool) {
+      let mut headers = HeaderMap::new();
+
+      for value in accept_encoding {
+        headers.append(header::ACCEPT_ENCODING, HeaderValue::from_static(value));
+      }
+
+      assert_eq!(Server::accepts_zstd(&headers), expected);
+    }
+
+    case(&[], false);
+    case(&["gzip, deflate"], false);
+    case(&["zstd"], true);
+    case(&["gzip, deflate, br, zstd"], true);
+    case(&["gzip", "ZSTD"], true);
+    case(&["zstd;q=0.5"], true);
+    case(&["zstd;q=0"], false);
+    case(&["zstdx"], false);
+  }
+
+  #[test]
+  fn upload_and_delete() {
+    let packages = tempdir();
+
+    let output = packages.join("comic.package");
+
+    subcommand::package::Package {
+      compress: false,
+      optimize: Default::default(),
+      root: "tests/packages/comic".into(),
+      output: output.clone(),
+      thumbnails: false,
+    }
+    .run()
+    .unwrap();
+
+    let body = Bytes::from(fs::read(&output).unwrap());
+
+    let library = tempdir();
+
+    Runtime::new().unwrap().block_on(async {
+      let node = Arc::new(
+        Node::new(
+          Ipv4Addr::LOCALHOST.into(),
+          None,
+          Limits::default(),
+          None,
+          BTreeMap::new(),
+          0,
+          None,
+        )
+        .await
+        .unwrap(),
+      );
+
+      let admin = Extension(Arc::new(Admin::new(Some("secret"))));
+
+      let catalog = Extension(Arc::new(Catalog::new(
+        node.clone(),
+        BTreeMap::new(),
+        Some(Library::new(library.path_utf8().into())),
+      )));
+
+      let mut headers = HeaderMap::new();
+
+      headers.insert(
+        header::AUTHORIZATION,
+        HeaderValue::from_static("Bearer secret"),
+      );
+
+      assert_eq!(
+        Server::upload(catalog.clone(), Bytes::from_static(b"foo"))
+          .await
+          .unwrap_err()
+          .into_response()
+          .status(),
+        StatusCode::BAD_REQUEST,
+      );
+
+      let (status, Json(hash)) = Server::upload(catalog.clone(), body).await.unwrap();
+
+      assert_eq!(status, StatusCode::CREATED);
+      assert!(node.packages().await.contains_key(&hash));
+      assert!(library.join(format!("{hash}.package")).is_file());
+
+      let Json(packages) = api::packages(Extension(node.clone())).await;
+      assert_eq!(packages.keys().collect::<Vec<&Hash>>(), [&hash]);
+
+      assert_eq!(
+        Server::delete(
+          admin.clone(),
+          catalog.clone(),
+          headers.clone(),
+          Path(DeserializeFromStr(hash)),
+        )
+        .await
+        .unwrap(),
+        StatusCode::NO_CONTENT,
+      );
+
+      assert!(node.packages().await.is_empty());
+      assert!(!library.join(format!("{hash}.package")).exists());
+
+      assert_eq!(
+        Server::delete(admin, catalog, headers, Path(DeserializeFromStr(hash)))
+          .await
+          .unwrap_err()
+          .into_response()
+          .status(),
+        StatusCode::NOT_FOUND,
+      );
+    });
+  }
+
+  #[test]
+  fn local_packages_are_not_stored() {
+    let tempdir = tempdir();
+
+    let output = tempdir.join("comic.package");
+
+    subcommand::package::Package {
+      compress: false,
+      optimize: Default::default(),
+      root: "tests/packages/comic".into(),
+      output: output.clone(),
+      thumbnails: false,
+    }
+    .run()
+    .unwrap();
+
+    let package = Arc::new(Package::load(&output).unwrap());
+
+    let data_dir = tempdir.join("data");
+
+    Runtime::new().unwrap().block_on(async {
+      let node = Arc::new(
+        Node::new(
+          Ipv4Addr::LOCALHOST.into(),
+          Some(&data_dir),
+          Limits::default(),
+          None,
+          BTreeMap::new(),
+          0,
+          None,
+        )
+        .await
+        .unwrap(),
+      );
+
+      let orphan = node.store().unwrap().insert(b"orphan").unwrap();
+
+      let catalog = Catalog::new(node.clone(), [(package.hash, package.clone())].into(), None);
+
+      catalog.load().await;
+
+      assert!(node.packages().await.contains_key(&package.hash));
+
+      assert!(!node.store().unwrap().contains(orphan));
+
+      assert_eq!(fs::read_dir(data_dir.join("blobs")).unwrap().count(), 0);
+    });
+  }
+
+  #[test]
+  fn uploads_are_authorized_before_reading_body() {
+    Runtime::new().unwrap().block_on(async {
+      let node = Arc::new(
+        Node::new(
+          Ipv4Addr::LOCALHOST.into(),
+          None,
+          Limits::default(),
+          None,
+          BTreeMap::new(),
+          0,
+          None,
+        )
+        .await
+        .unwrap(),
+      );
+
+      let catalog = Arc::new(Catalog::new(node.clone(), BTreeMap::new(), None));
+
+      let upload = |body| Request::post("/").body(body).unwrap();
+
+      let response = Server::router(Admin::new(None), catalog.clone(), node.clone())
+        .oneshot(upload(Body::from("foo")))
+        .await
+        .unwrap();
+
+      assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+
+      // a body that never ends, so the request only completes if the body
+      // is never read
+      let body = Body::from_stream(futures_util::stream::pending::<io::Result<Bytes>>());
+
+      let response = tokio::time::timeout(
+        Duration::from_secs(10),
+        Server::router(Admin::new(Some("secret")), catalog, node).oneshot(upload(body)),
+      )
+      .await
+      .unwrap()
+      .unwrap();
+
+      assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    });
+  }
+
+  #[test]
+  fn saving_requires_admin_token() {
+    Runtime::new().unwrap().block_on(async {
+      async fn case(token: Option<&str>, request: Request) -> StatusCode {
+        let node = Arc::new(
+          Node::new(
+            Ipv4Addr::LOCALHOST.into(),
+            None,
+            Limits::default(),
+            None,
+            BTreeMap::new(),
+            0,
+            None,
+          )
+          .await
+          .unwrap(),
+        );
+
+        let catalog = Arc::new(Catalog::new(node.clone(), BTreeMap::new(), None));
+
+        Server::router(Admin::new(token), catalog, node)
+          .oneshot(request)
+          .await
+          .unwrap()
+          .status()
+      }
+
+      let uri = format!("/peer/{}/{}", Id::from([1; Id::LEN]), Hash::bytes(b"foo"));
+
+      let form = |body: &'static str| {
+        Request::post(&uri)
+          .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+          .body(Body::from(body))
+          .unwrap()
+      };
+
+      let bearer = |token: &str| {
+        Request::post(&uri)
+          .header(header::AUTHORIZATION, format!("Bearer {token}"))
+          .body(Body::empty())
+          .unwrap()
+      };
+
+      assert_eq!(
+        case(None, form("token=secret")).await,
+        StatusCode::FORBIDDEN
+      );
+      assert_eq!(
+        case(Some("secret"), bearer("")).await,
+        StatusCode::UNAUTHORIZED
+      );
+      assert_eq!(
+        case(Some("secret"), form("token=foo")).await,
+        StatusCode::UNAUTHORIZED
+      );
+
+      // authorized requests get as far as saving, which fails because the
+      // node has no data directory
+      assert_eq!(
+        case(Some("secret"), form("token=secret")).await,
+        StatusCode::CONFLICT
+      );
+      assert_eq!(
+        case(Some("secret"), bearer("secret")).await,
+        StatusCode::CONFLICT
+      );
+    });
+  }
+
+  #[test]
+  fn admin_token_requires_storage() {
+    assert!(Server::try_parse_from(["server", "--admin-token", "foo"]).is_err());
+
+    for storage in ["--library", "--data-dir"] {
+      assert!(Server::try_parse_from(["server", "--admin-token", "foo", storage, "dir"]).is_ok());
+    }
+  }
 
   #[test]
   fn package_load_error() {
@@ -250,10 +1149,26 @@ mod tests {
     assert_matches!(
       Server {
         address: Ipv4Addr::new(0, 0, 0, 0).into(),
-        bootstrap: None,
+        admin_token: None,
+        bootstrap: Vec::new(),
+        bootstrap_file: None,
+        data_dir: None,
+        discovery_group: Vec::new(),
+        discovery_interface: None,
+        discovery_port: discovery::DEFAULT_PORT,
         http_port: 80,
+        network_key: None,
+        no_discovery: false,
+        library: None,
         open: false,
         packages: vec![package.clone()],
+        download_limit: None,
+        max_connections: limits::DEFAULT_MAX_CONNECTIONS,
+        max_streams: limits::DEFAULT_MAX_STREAMS,
+        upload_limit: None,
+        relay: false,
+        relay_bandwidth: None,
+        relay_max_streams: relay::DEFAULT_MAX_STREAMS,
       }
       .run()
       .unwrap_err(),
@@ -261,4 +1176,157 @@ mod tests {
       if path == package,
     );
   }
+
+  #[test]
+  fn bootstrap_file() {
+    let tempdir = tempdir();
+
+    let id = "0f89ce0b671f7277b105035ea88341a81c5fceaed092eab29721fe6f86807133";
+
+    tempdir.write(
+      "peers",
+      format!(
+        "# bootstrap peers\n{id}@1.2.3.4:5\n\n  {id}@[1:2:3:4:5:6:7:8]:6  \n{id}@example.com:7\n"
+      ),
+    );
+
+    assert_eq!(
+      Server::load_bootstrap_file(&tempdir.join("peers")).unwrap(),
+      [
+        format!("{id}@1.2.3.4:5").parse::<Bootstrap>().unwrap(),
+        format!("{id}@[1:2:3:4:5:6:7:8]:6").parse().unwrap(),
+        format!("{id}@example.com:7").parse().unwrap(),
+      ],
+    );
+
+    tempdir.write("invalid", format!("{id}@1.2.3.4:5\nfoo\n"));
+
+    assert_matches!(
+      Server::load_bootstrap_file(&tempdir.join("invalid")).unwrap_err(),
+      Error::BootstrapPeer { line: 2, .. },
+    );
+  }
+
+  #[test]
+  fn discovery_config() {
+    #[track_caller]
+    fn case(args: &[&str]) -> Result<Option<discovery::Config>> {
+      Server::try_parse_from(iter::once("server").chain(args.iter().copied()))
+        .unwrap()
+        .discovery()
+    }
+
+    assert_eq!(case(&[]).unwrap(), Some(discovery::Config::default()));
+
+    assert_eq!(case(&["--no-discovery"]).unwrap(), None);
+
+    assert_eq!(
+      case(&[
+        "--discovery-group",
+        "239.1.2.3",
+        "--discovery-group",
+        "ff02::1:2:3",
+        "--discovery-port",
+        "5000",
+        "--discovery-interface",
+        "3",
+      ])
+      .unwrap(),
+      Some(discovery::Config {
+        groups: vec![
+          Ipv4Addr::new(239, 1, 2, 3).into(),
+          "ff02::1:2:3".parse().unwrap(),
+        ],
+        interface: Some(3),
+        port: 5000,
+      }),
+    );
+
+    assert_matches!(
+      case(&["--discovery-group", "10.0.0.1"]),
+      Err(Error::DiscoveryGroup { group, .. }) if group == Ipv4Addr::new(10, 0, 0, 1),
+    );
+
+    assert!(
+      Server::try_parse_from(["server", "--no-discovery", "--discovery-port", "5000"]).is_err()
+    );
+  }
+
+  #[test]
+  fn limits() {
+    #[track_caller]
+    fn case(args: &[&str]) -> Limits {
+      Server::try_parse_from(iter::once("server").chain(args.iter().copied()))
+        .unwrap()
+        .limits()
+    }
+
+    assert_eq!(case(&[]), Limits::default());
+
+    assert_eq!(
+      case(&[
+        "--download-limit",
+        "1000",
+        "--upload-limit",
+        "2000",
+        "--max-connections",
+        "3",
+        "--max-streams",
+        "4",
+      ]),
+      Limits {
+        download: Some(1000),
+        max_connections: 3,
+        max_streams: 4,
+        upload: Some(2000),
+      },
+    );
+
+    for flag in [
+      "--download-limit",
+      "--upload-limit",
+      "--max-connections",
+      "--max-streams",
+    ] {
+      assert!(Server::try_parse_from(["server", flag, "0"]).is_err());
+    }
+  }
+
+  #[test]
+  fn relay_config() {
+    #[track_caller]
+    fn case(args: &[&str]) -> Option<relay::Config> {
+      Server::try_parse_from(iter::once("server").chain(args.iter().copied()))
+        .unwrap()
+        .relay()
+    }
+
+    assert_eq!(case(&[]), None);
+
+    assert_eq!(
+      case(&["--relay"]),
+      Some(relay::Config {
+        bandwidth: None,
+        max_streams: relay::DEFAULT_MAX_STREAMS,
+      }),
+    );
+
+    assert_eq!(
+      case(&[
+        "--relay",
+        "--relay-bandwidth",
+        "1000",
+        "--relay-max-streams",
+        "8"
+      ]),
+      Some(relay::Config {
+        bandwidth: Some(1000),
+        max_streams: 8,
+      }),
+    );
+
+    assert!(Server::try_parse_from(["server", "--relay-bandwidth", "1000"]).is_err());
+
+    assert!(Server::try_parse_from(["server", "--relay", "--relay-bandwidth", "0"]).is_err());
+  }
 }
@@ -0,0 +1,92 @@
+use super::*;
+
+pub(crate) struct Admin {
+  token: Option<blake3::Hash>,
+}
+
+impl Admin {
+  pub(crate) fn new(token: Option<&str>) -> Self {
+    Self {
+      token: token.map(|token| blake3::hash(token.as_bytes())),
+    }
+  }
+
+  pub(crate) fn is_enabled(&self) -> bool {
+    self.token.is_some()
+  }
+
+  pub(crate) fn bearer(headers: &HeaderMap) -> Option<&str> {
+    headers
+      .get(header::AUTHORIZATION)
+      .and_then(|value| value.to_str().ok())
+      .and_then(|value| value.strip_prefix("Bearer "))
+  }
+
+  pub(crate) fn authorize(&self, headers: &HeaderMap) -> ServerResult<()> {
+    self.authorize_token(Self::bearer(headers))
+  }
+
+  pub(crate) fn authorize_token(&self, bearer: Option<&str>) -> ServerResult<()> {
+    let Some(token) = self.token else {
+      return Err(ServerError::Forbidden {
+        message: "admin API disabled".into(),
+      });
+    };
+
+    let authorized = bearer.is_some_and(|bearer| blake3::hash(bearer.trim().as_bytes()) == token);
+
+    if authorized {
+      Ok(())
+    } else {
+      Err(ServerError::Unauthorized)
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn authorize() {
+    #[track_caller]
+    fn case(
+      token: Option<&str>,
+      authorization: Option<&'static str>,
+      expected: Option<StatusCode>,
+    ) {
+      let mut headers = HeaderMap::new();
+
+      if let Some(authorization) = authorization {
+        headers.insert(
+          header::AUTHORIZATION,
+          HeaderValue::from_static(authorization),
+        );
+      }
+
+      assert_eq!(
+        Admin::new(token)
+          .authorize(&headers)
+          .err()
+          .map(|err| err.into_response().status()),
+        expected,
+      );
+    }
+
+    case(None, None, Some(StatusCode::FORBIDDEN));
+    case(None, Some("Bearer foo"), Some(StatusCode::FORBIDDEN));
+    case(Some("foo"), None, Some(StatusCode::UNAUTHORIZED));
+    case(
+      Some("foo"),
+      Some("Bearer bar"),
+      Some(StatusCode::UNAUTHORIZED),
+    );
+    case(
+      Some("foo"),
+      Some("Basic foo"),
+      Some(StatusCode::UNAUTHORIZED),
+    );
+    case(Some("foo"), Some("foo"), Some(StatusCode::UNAUTHORIZED));
+    case(Some("foo"), Some("Bearer foo"), None);
+  }
+}
@@ -0,0 +1,288 @@
+use super::*;
+
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+pub(crate) struct File {
+  pub(crate) compressed: bool,
+  pub(crate) content_type: String,
+  pub(crate) hash: Hash,
+  pub(crate) path: String,
+  pub(crate) size: u64,
+}
+
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+pub(crate) struct NodeStatus {
+  pub(crate) incoming: u64,
+  pub(crate) limits: Limits,
+  pub(crate) local: BTreeSet<Id>,
+  pub(crate) peer: Peer,
+  pub(crate) pool: pool::Stats,
+  pub(crate) public_address: Option<SocketAddr>,
+  pub(crate) relay: Option<relay::Stats>,
+  pub(crate) relayed: BTreeMap<Id, Peer>,
+  pub(crate) received: u64,
+  pub(crate) sent: u64,
+}
+
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+pub(crate) struct PackageFiles {
+  pub(crate) files: Vec<File>,
+  pub(crate) hash: Hash,
+  pub(crate) manifest: Manifest,
+}
+
+#[derive(Debug, Default, Deserialize, PartialEq, Serialize)]
+pub(crate) struct PeerResults {
+  pub(crate) manifests: BTreeMap<Hash, Manifest>,
+  pub(crate) missing: BTreeSet<Hash>,
+}
+
+#[derive(Debug, Default, Deserialize, PartialEq, Serialize)]
+pub(crate) struct SearchResults {
+  pub(crate) failed: BTreeSet<Id>,
+  pub(crate) local: BTreeMap<Hash, Manifest>,
+  pub(crate) remote: BTreeMap<Id, BTreeMap<Hash, Manifest>>,
+}
+
+pub(crate) fn router() -> Router {
+  Router::new()
+    .route("/node", get(node))
+    .route("/packages", get(packages))
+    .route("/packages/:package", get(package))
+    .route("/peers/:peer", get(peer))
+    .route("/search", get(search))
+}
+
+async fn node(node: Extension<Arc<Node>>) -> Json<NodeStatus> {
+  Json(NodeStatus {
+    incoming: node.incoming(),
+    limits: node.limits(),
+    local: node.local.read().await.keys().copied().collect(),
+    peer: node.peer(),
+    pool: node.pool_stats(),
+    public_address: node.public_address().await,
+    relay: node.relay_stats(),
+    relayed: node.relayed().await,
+    received: node.received.load(atomic::Ordering::Relaxed),
+    sent: node.sent.load(atomic::Ordering::Relaxed),
+  })
+}
+
+pub(crate) async fn packages(node: Extension<Arc<Node>>) -> Json<BTreeMap<Hash, Manifest>> {
+  Json(
+    node
+      .packages()
+      .await
+      .iter()
+      .map(|(hash, package)| (*hash, package.manifest.clone()))
+      .collect(),
+  )
+}
+
+async fn package(
+  node: Extension<Arc<Node>>,
+  Path(DeserializeFromStr(hash)): Path<DeserializeFromStr<Hash>>,
+) -> ServerResult<Json<PackageFiles>> {
+  let packages = node.packages().await;
+
+  let package = packages.get(&hash).ok_or_else(|| ServerError::NotFound {
+    message: format!("package {hash} not found"),
+  })?;
+
+  let files = package
+    .paths()
+    .into_iter()
+    .filter_map(|(path, hash)| {
+      let (content_type, content) = package.content(&path)?;
+      Some(File {
+        compressed: content.compressed,
+        content_type: content_type.to_string(),
+        hash,
+        path,
+        size: content.data.len().into_u64(),
+      })
+    })
+    .collect();
+
+  Ok(Json(PackageFiles {
+    files,
+    hash,
+    manifest: package.manifest.clone(),
+  }))
+}
+
+async fn peer(
+  node: Extension<Arc<Node>>,
+  Path(DeserializeFromStr(peer)): Path<DeserializeFromStr<Id>>,
+) -> ServerResult<Json<PeerResults>> {
+  Ok(Json(Server::search(&node, peer).await?))
+}
+
+async fn search(
+  node: Extension<Arc<Node>>,
+  Query(query): Query<SearchQuery>,
+) -> ServerResult<Json<SearchResults>> {
+  Ok(Json(Server::query(&node, &query.q).await?))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn search() {
+    let tempdir = tempdir();
+
+    let output = tempdir.join("comic.package");
+
+    subcommand::package::Package {
+      compress: false,
+      optimize: Default::default(),
+      root: "tests/packages/comic".into(),
+      output: output.clone(),
+      thumbnails: false,
+    }
+    .run()
+    .unwrap();
+
+    let package = Arc::new(crate::package::Package::load(&output).unwrap());
+
+    Runtime::new().unwrap().block_on(async {
+      let node = Arc::new(
+        Node::new(
+          Ipv4Addr::LOCALHOST.into(),
+          None,
+          Limits::default(),
+          None,
+          BTreeMap::new(),
+          0,
+          None,
+        )
+        .await
+        .unwrap(),
+      );
+
+      let query = |q: &str| Query(SearchQuery { q: q.into() });
+
+      let Json(results) = super::search(Extension(node.clone()), query("comic"))
+        .await
+        .unwrap();
+      assert_eq!(results, SearchResults::default());
+
+      node
+        .set_packages(
+          [(package.hash, package.clone())].into(),
+          BTreeSet::new(),
+          BTreeMap::new(),
+        )
+        .await;
+
+      let Json(results) = super::search(Extension(node.clone()), query("test com"))
+        .await
+        .unwrap();
+      assert_eq!(
+        results,
+        SearchResults {
+          failed: BTreeSet::new(),
+          local: [(package.hash, package.manifest.clone())].into(),
+          remote: BTreeMap::new(),
+        },
+      );
+
+      let Json(results) = super::search(Extension(node.clone()), query(""))
+        .await
+        .unwrap();
+      assert_eq!(results, SearchResults::default());
+
+      assert_matches!(
+        super::search(
+          Extension(node.clone()),
+          query(&"a".repeat(Server::MAX_QUERY_LEN + 1)),
+        )
+        .await,
+        Err(ServerError::BadRequest { .. }),
+      );
+
+      let Json(results) = super::search(Extension(node), query("batman"))
+        .await
+        .unwrap();
+      assert_eq!(results, SearchResults::default());
+    });
+  }
+
+  #[test]
+  fn package() {
+    let tempdir = tempdir();
+
+    let output = tempdir.join("comic.package");
+
+    subcommand::package::Package {
+      compress: true,
+      optimize: Default::default(),
+      root: "tests/packages/comic".into(),
+      output: output.clone(),
+      thumbnails: false,
+    }
+    .run()
+    .unwrap();
+
+    let package = Arc::new(crate::package::Package::load(&output).unwrap());
+
+    Runtime::new().unwrap().block_on(async {
+      let node = Arc::new(
+        Node::new(
+          Ipv4Addr::LOCALHOST.into(),
+          None,
+          Limits::default(),
+          None,
+          [(package.hash, package.clone())].into(),
+          0,
+          None,
+        )
+        .await
+        .unwrap(),
+      );
+
+      let Json(packages) = packages(Extension(node.clone())).await;
+
+      assert_eq!(packages, [(package.hash, package.manifest.clone())].into());
+
+      let Json(files) = super::package(
+        Extension(node.clone()),
+        Path(DeserializeFromStr(package.hash)),
+      )
+      .await
+      .unwrap();
+
+      assert_eq!(files.hash, package.hash);
+      assert_eq!(files.manifest, package.manifest);
+      assert_eq!(
+        files
+          .files
+          .iter()
+          .map(|file| file.path.as_str())
+          .collect::<Vec<&str>>(),
+        ["0.jpg", "1.jpg", "2.jpg"],
+      );
+
+      for file in &files.files {
+        assert_eq!(file.content_type, "image/jpeg");
+        assert_eq!(file.size, package.files[&file.hash].data.len().into_u64());
+      }
+
+      let json = serde_json::to_string(&files).unwrap();
+
+      assert!(json.contains(&format!("\"hash\":\"{}\"", package.hash)));
+
+      assert_eq!(serde_json::from_str::<PackageFiles>(&json).unwrap(), files);
+
+      assert_eq!(
+        super::package(Extension(node), Path(DeserializeFromStr(Hash::bytes(&[]))))
+          .await
+          .unwrap_err()
+          .into_response()
+          .status(),
+        StatusCode::NOT_FOUND,
+      );
+    });
+  }
+}
@@ -0,0 +1,266 @@
+use {
+  super::*,
+  tokio::sync::{Mutex, MutexGuard},
+};
+
+pub(crate) struct Catalog {
+  library: Option<Mutex<Library>>,
+  node: Arc<Node>,
+  packages: BTreeMap<Hash, Arc<Package>>,
+  pinned: Mutex<BTreeMap<Hash, Arc<Package>>>,
+}
+
+impl Catalog {
+  pub(crate) fn new(
+    node: Arc<Node>,
+    packages: BTreeMap<Hash, Arc<Package>>,
+    library: Option<Library>,
+  ) -> Self {
+    Self {
+      library: library.map(Mutex::new),
+      node,
+      packages,
+      pinned: Mutex::default(),
+    }
+  }
+
+  pub(crate) fn merge(
+    packages: &BTreeMap<Hash, Arc<Package>>,
+    library: Option<&Library>,
+  ) -> BTreeMap<Hash, Arc<Package>> {
+    let mut packages = packages.clone();
+
+    for package in library.into_iter().flat_map(Library::packages) {
+      packages.insert(package.hash, package.clone());
+    }
+
+    packages
+  }
+
+  pub(crate) async fn add(&self, package: &Package, bytes: &[u8]) -> ServerResult<()> {
+    let mut library = self.library().await?;
+
+    tokio::task::block_in_place(|| library.add(package.hash, bytes))
+      .map_err(|source| ServerError::Library { source })?;
+
+    self.rescan(&mut library).await?;
+
+    Ok(())
+  }
+
+  pub(crate) async fn load(&self) {
+    let mut pinned = self.pinned.lock().await;
+
+    if let Some(store) = self.node.store() {
+      tokio::task::block_in_place(|| match store.pinned() {
+        Ok(hashes) => {
+          for hash in hashes {
+            match store.package(hash) {
+              Ok(package) => {
+                pinned.insert(hash, Arc::new(package));
+              }
+              Err(err) => err.report(),
+            }
+          }
+        }
+        Err(err) => err.report(),
+      });
+
+      tokio::task::block_in_place(|| Self::collect(store));
+    }
+
+    let library = self.lock_library().await;
+
+    self.publish(library.as_deref(), &pinned).await;
+  }
+
+  pub(crate) async fn reload(&self) -> ServerResult<bool> {
+    let mut library = self.library().await?;
+    self.rescan(&mut library).await
+  }
+
+  pub(crate) async fn remove(&self, hash: Hash) -> ServerResult<()> {
+    let library = self.lock_library().await;
+
+    let mut removed = match &library {
+      Some(library) => tokio::task::block_in_place(|| library.remove(hash))
+        .map_err(|source| ServerError::Library { source })?,
+      None => false,
+    };
+
+    let mut pinned = self.pinned.lock().await;
+
+    if pinned.remove(&hash).is_some() {
+      if let Some(store) = self.node.store() {
+        store
+          .unpin(hash)
+          .map_err(|source| ServerError::Store { source })?;
+
+        tokio::task::block_in_place(|| Self::collect(store));
+      }
+      removed = true;
+    }
+
+    if !removed {
+      return Err(ServerError::NotFound {
+        message: format!("package {hash} not in library"),
+      });
+    }
+
+    let mut library = library;
+
+    if let Some(library) = &mut library {
+      tokio::task::block_in_place(|| library.scan())
+        .map_err(|source| ServerError::Library { source })?;
+    }
+
+    self.publish(library.as_deref(), &pinned).await;
+
+    Ok(())
+  }
+
+  pub(crate) async fn save(&self, peer: Id, hash: Hash) -> ServerResult<()> {
+    let store = self.node.store().ok_or_else(|| ServerError::Conflict {
+      message: "no data directory configured".into(),
+    })?;
+
+    store
+      .pin(hash)
+      .map_err(|source| ServerError::Store { source })?;
+
+    let package = match self.download(store, peer, hash).await {
+      Ok(package) => package,
+      Err(err) => {
+        match store.unpin(hash) {
+          Ok(_) => tokio::task::block_in_place(|| Self::collect(store)),
+          Err(err) => err.report(),
+        }
+        return Err(err);
+      }
+    };
+
+    let library = self.lock_library().await;
+
+    let mut pinned = self.pinned.lock().await;
+
+    pinned.insert(hash, Arc::new(package));
+
+    self.publish(library.as_deref(), &pinned).await;
+
+    Ok(())
+  }
+
+  async fn download(&self, store: &Store, peer: Id, hash: Hash) -> ServerResult<Package> {
+    let manifest = self
+      .node
+      .file(peer, hash)
+      .await
+      .map_err(|source| ServerError::Node { source })?
+      .ok_or_else(|| ServerError::NotFound {
+        message: format!("package {hash} not found on peer {peer}"),
+      })?;
+
+    store
+      .insert(&manifest)
+      .map_err(|source| ServerError::Store { source })?;
+
+    let manifest = store
+      .manifest(hash)
+      .map_err(|source| ServerError::Store { source })?
+      .ok_or_else(|| ServerError::NotFound {
+        message: format!("package {hash} not found on peer {peer}"),
+      })?;
+
+    let semaphore = Arc::new(Semaphore::new(Server::MAX_CONCURRENT_FETCHES));
+
+    let mut tasks = JoinSet::new();
+
+    for file in manifest.files() {
+      if store.contains(file) {
+        continue;
+      }
+
+      let node = self.node.clone();
+      let semaphore = semaphore.clone();
+      tasks.spawn(async move {
+        let _permit = semaphore.acquire().await.unwrap();
+        (file, node.file(peer, file).await)
+      });
+    }
+
+    while let Some(result) = tasks.join_next().await {
+      let (file, content) = result.unwrap();
+
+      let content = content
+        .map_err(|source| ServerError::Node { source })?
+        .ok_or_else(|| ServerError::NotFound {
+          message: format!("file {file} of package {hash} not found on peer {peer}"),
+        })?;
+
+      tokio::task::block_in_place(|| store.insert(&content))
+        .map_err(|source| ServerError::Store { source })?;
+    }
+
+    tokio::task::block_in_place(|| store.package(hash))
+      .map_err(|source| ServerError::Store { source })
+  }
+
+  async fn library(&self) -> ServerResult<MutexGuard<'_, Library>> {
+    self
+      .lock_library()
+      .await
+      .ok_or_else(|| ServerError::Conflict {
+        message: "no library configured".into(),
+      })
+  }
+
+  async fn lock_library(&self) -> Option<MutexGuard<'_, Library>> {
+    match &self.library {
+      Some(library) => Some(library.lock().await),
+      None => None,
+    }
+  }
+
+  async fn publish(&self, library: Option<&Library>, pinned: &BTreeMap<Hash, Arc<Package>>) {
+    let mut packages = pinned.clone();
+
+    packages.extend(Self::merge(&self.packages, library));
+
+    let visibility = library.map(Library::visibility).unwrap_or_default();
+
+    self
+      .node
+      .set_packages(packages, pinned.keys().copied().collect(), visibility)
+      .await;
+  }
+
+  async fn rescan(&self, library: &mut Library) -> ServerResult<bool> {
+    let changed = tokio::task::block_in_place(|| library.scan())
+      .map_err(|source| ServerError::Library { source })?;
+
+    if changed {
+      log::info!("reloaded library `{}`", library.dir());
+      let pinned = self.pinned.lock().await;
+      self.publish(Some(library), &pinned).await;
+    }
+
+    Ok(changed)
+  }
+
+  // local packages are served from their own files, so the store only holds
+  // pinned packages, and only needs collecting when pins are removed
+  fn collect(store: &Store) {
+    match store.gc() {
+      Ok(collected) => {
+        if collected.blobs > 0 {
+          log::info!(
+            "collected {} unreferenced blobs totaling {} bytes",
+            collected.blobs,
+            collected.bytes,
+          );
+        }
+      }
+      Err(err) => err.report(),
+    }
+  }
+}
@@ -3,17 +3,49 @@ use super::*;
 #[derive(Debug, Snafu)]
 #[snafu(context(suffix(false)), visibility(pub(crate)))]
 pub(crate) enum ServerError {
+  BadRequest { message: String },
+  Conflict { message: String },
+  Forbidden { message: String },
+  Library { source: Error },
   NotFound { message: String },
   Node { source: node::Error },
+  PackageInvalid { source: crate::package::Error },
+  Store { source: Error },
+  Unauthorized,
 }
 
 impl IntoResponse for ServerError {
   fn into_response(self) -> Response {
     match self {
+      Self::BadRequest { message } => (StatusCode::BAD_REQUEST, message).into_response(),
+      Self::Conflict { message } => (StatusCode::CONFLICT, message).into_response(),
+      Self::Forbidden { message } => (StatusCode::FORBIDDEN, message).into_response(),
+      Self::Library { source } => {
+        source.report();
+        (StatusCode::INTERNAL_SERVER_ERROR, "Internal Server Error").into_response()
+      }
       Self::NotFound { message } => (StatusCode::NOT_FOUND, message).into_response(),
+      Self::Node { source } if source.is_timeout() => {
+        (StatusCode::GATEWAY_TIMEOUT, "Peer Timed Out").into_response()
+      }
       Self::Node { .. } => {
         (StatusCode::INTERNAL_SERVER_ERROR, "Internal Server Error").into_response()
       }
+      Self::PackageInvalid { source } => (
+        StatusCode::BAD_REQUEST,
+        format!("invalid package: {source}"),
+      )
+        .into_response(),
+      Self::Store { source } => {
+        source.report();
+        (StatusCode::INTERNAL_SERVER_ERROR, "Internal Server Error").into_response()
+      }
+      Self::Unauthorized => (
+        StatusCode::UNAUTHORIZED,
+        [(header::WWW_AUTHENTICATE, "Bearer")],
+        "Unauthorized",
+      )
+        .into_response(),
     }
   }
 }
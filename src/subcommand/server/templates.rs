@@ -1,9 +1,21 @@
 use super::*;
 
+#[derive(Boilerplate)]
+pub(crate) struct LibraryHtml {
+  pub(crate) packages: Arc<BTreeMap<Hash, Arc<Package>>>,
+  pub(crate) visibility: Arc<BTreeMap<Hash, Visibility>>,
+}
+
 #[derive(Boilerplate)]
 pub(crate) struct NodeHtml {
-  pub(crate) local: BTreeSet<Id>,
+  pub(crate) incoming: u64,
+  pub(crate) limits: Limits,
+  pub(crate) local: BTreeMap<Id, Contact>,
   pub(crate) peer: Peer,
+  pub(crate) pool: pool::Stats,
+  pub(crate) public_address: Option<SocketAddr>,
+  pub(crate) relay: Option<relay::Stats>,
+  pub(crate) relayed: BTreeMap<Id, Peer>,
   pub(crate) received: u64,
   pub(crate) sent: u64,
 }
@@ -11,16 +23,26 @@ pub(crate) struct NodeHtml {
 #[derive(Boilerplate)]
 pub(crate) struct PackageHtml {
   pub(crate) package: Arc<Package>,
+  pub(crate) visibility: Visibility,
 }
 
 #[derive(Boilerplate)]
 pub(crate) struct PageHtml<T: Display> {
   pub(crate) main: T,
-  pub(crate) packages: Arc<BTreeMap<Hash, Package>>,
+  pub(crate) packages: Arc<BTreeMap<Hash, Arc<Package>>>,
+  pub(crate) pinned: Arc<BTreeSet<Hash>>,
+}
+
+#[derive(Boilerplate)]
+pub(crate) struct QueryHtml {
+  pub(crate) query: String,
+  pub(crate) results: SearchResults,
 }
 
 #[derive(Boilerplate)]
 pub(crate) struct SearchHtml {
-  pub(crate) manifests: BTreeMap<Hash, Manifest>,
+  pub(crate) admin: bool,
+  pub(crate) error: Option<String>,
   pub(crate) peer: Id,
+  pub(crate) results: PeerResults,
 }
@@ -10,7 +10,11 @@ pub(crate) enum Media {
 }
 
 impl Template {
-  pub(crate) fn manifest(self, hashes: &HashMap<Utf8PathBuf, (Hash, u64)>) -> Manifest {
+  pub(crate) fn manifest(
+    self,
+    hashes: &HashMap<Utf8PathBuf, (Hash, u64)>,
+    thumbnails: Option<manifest::Thumbnails>,
+  ) -> Manifest {
     let media = match self.media {
       Media::Comic { pages } => super::Media::Comic {
         pages: pages
@@ -23,6 +27,25 @@ impl Template {
     Manifest {
       name: self.name,
       media,
+      thumbnails,
+    }
+  }
+
+  pub(crate) fn rename(&mut self, renamed: &HashMap<Utf8PathBuf, Utf8PathBuf>) {
+    match &mut self.media {
+      Media::Comic { pages } => {
+        for page in pages {
+          if let Some(path) = renamed.get(page) {
+            page.clone_from(path);
+          }
+        }
+      }
+    }
+  }
+
+  pub(crate) fn pages(&self) -> &[Utf8PathBuf] {
+    match &self.media {
+      Media::Comic { pages } => pages,
     }
   }
 }
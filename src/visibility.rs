@@ -0,0 +1,106 @@
+use super::*;
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Visibility {
+  Allow(BTreeSet<Id>),
+  Private,
+  #[default]
+  Public,
+}
+
+impl Visibility {
+  pub(crate) fn restrict(&self, other: &Self) -> Self {
+    match (self, other) {
+      (Self::Private, _) | (_, Self::Private) => Self::Private,
+      (Self::Public, visibility) | (visibility, Self::Public) => visibility.clone(),
+      (Self::Allow(a), Self::Allow(b)) => Self::Allow(a.intersection(b).copied().collect()),
+    }
+  }
+
+  pub(crate) fn allows(&self, id: Option<Id>) -> bool {
+    match self {
+      Self::Allow(ids) => id.is_some_and(|id| ids.contains(&id)),
+      Self::Private => false,
+      Self::Public => true,
+    }
+  }
+}
+
+impl Display for Visibility {
+  fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+    match self {
+      Self::Allow(ids) if ids.len() == 1 => write!(f, "shared with 1 network member"),
+      Self::Allow(ids) => write!(f, "shared with {} network members", ids.len()),
+      Self::Private => write!(f, "private"),
+      Self::Public => write!(f, "public"),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn allows() {
+    let a = Id::from([1; Id::LEN]);
+    let b = Id::from([2; Id::LEN]);
+
+    assert!(Visibility::Public.allows(Some(a)));
+    assert!(Visibility::Public.allows(None));
+    assert!(!Visibility::Private.allows(Some(a)));
+    assert!(Visibility::Allow([a].into()).allows(Some(a)));
+    assert!(!Visibility::Allow([a].into()).allows(Some(b)));
+    assert!(!Visibility::Allow([a].into()).allows(None));
+  }
+
+  #[test]
+  fn restrict() {
+    let a = Id::from([1; Id::LEN]);
+    let b = Id::from([2; Id::LEN]);
+
+    #[track_caller]
+    fn case(x: Visibility, y: Visibility, expected: Visibility) {
+      assert_eq!(x.restrict(&y), expected);
+      assert_eq!(y.restrict(&x), expected);
+    }
+
+    case(Visibility::Public, Visibility::Public, Visibility::Public);
+    case(Visibility::Public, Visibility::Private, Visibility::Private);
+    case(
+      Visibility::Public,
+      Visibility::Allow([a].into()),
+      Visibility::Allow([a].into()),
+    );
+    case(
+      Visibility::Private,
+      Visibility::Allow([a].into()),
+      Visibility::Private,
+    );
+    case(
+      Visibility::Allow([a, b].into()),
+      Visibility::Allow([b].into()),
+      Visibility::Allow([b].into()),
+    );
+  }
+
+  #[test]
+  fn yaml() {
+    fn from_str(yaml: &str) -> Visibility {
+      serde_yaml::with::singleton_map::deserialize(serde_yaml::Deserializer::from_str(yaml))
+        .unwrap()
+    }
+
+    let id = "0f89ce0b671f7277b105035ea88341a81c5fceaed092eab29721fe6f86807133";
+
+    assert_eq!(from_str("public"), Visibility::Public,);
+
+    assert_eq!(from_str("private"), Visibility::Private,);
+
+    assert_eq!(
+      from_str(&format!("allow: [{id}]")),
+      Visibility::Allow([id.parse().unwrap()].into()),
+    );
+  }
+}